@@ -0,0 +1,471 @@
+use crate::io::{MobcoinFbas, QuorumSet};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::collections::{BTreeSet, HashMap};
+
+/// A set of nodes given by their indices in an Fbas.
+pub(crate) type NodeSet = BTreeSet<usize>;
+
+/// A QuorumSet whose validators are indices in an Fbas.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+struct IndexedQuorumSet {
+    threshold: usize,
+    validators: Vec<usize>,
+    inner_quorum_sets: Vec<IndexedQuorumSet>,
+}
+
+/// The FBAS formed by the nodes of a MobcoinFbas and their QSets.
+/// Nodes are identified by their base64 encoded public keys. Validators that only appear in QSets
+/// are part of the FBAS as well but, like nodes with an empty QSet, can never be satisfied.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Fbas {
+    ids: Vec<String>,
+    quorum_sets: Vec<Option<IndexedQuorumSet>>,
+}
+
+impl IndexedQuorumSet {
+    /// A QSet with a threshold of 0 is never satisfied so that nodes without a QSet can't be
+    /// part of a quorum.
+    fn is_satisfied(&self, nodes: &NodeSet) -> bool {
+        let satisfied = self
+            .validators
+            .iter()
+            .filter(|validator| nodes.contains(validator))
+            .count()
+            + self
+                .inner_quorum_sets
+                .iter()
+                .filter(|inner_quorum_set| inner_quorum_set.is_satisfied(nodes))
+                .count();
+        self.threshold > 0 && satisfied >= self.threshold
+    }
+}
+
+impl Fbas {
+    pub(crate) fn from_mobcoin_fbas(fbas: &MobcoinFbas) -> Self {
+        let mut analysed = Self::default();
+        let mut indices = HashMap::new();
+        let known: Vec<(usize, &QuorumSet)> = fbas
+            .nodes()
+            .iter()
+            .filter_map(|node| {
+                let id = STANDARD.encode(node.public_key);
+                if indices.contains_key(&id) {
+                    warn!(
+                        "{} is listed more than once, ignoring {}.",
+                        id, node.hostname
+                    );
+                    return None;
+                }
+                Some((analysed.add_node(id, &mut indices), &node.quorum_set))
+            })
+            .collect();
+        for (index, quorum_set) in known {
+            analysed.quorum_sets[index] = Some(analysed.index_quorum_set(quorum_set, &mut indices));
+        }
+        analysed
+    }
+
+    fn add_node(&mut self, id: String, indices: &mut HashMap<String, usize>) -> usize {
+        let index = self.ids.len();
+        indices.insert(id.clone(), index);
+        self.ids.push(id);
+        self.quorum_sets.push(None);
+        index
+    }
+
+    fn index_quorum_set(
+        &mut self,
+        quorum_set: &QuorumSet,
+        indices: &mut HashMap<String, usize>,
+    ) -> IndexedQuorumSet {
+        let validators = quorum_set
+            .validators
+            .iter()
+            .map(|validator| match indices.get(validator) {
+                Some(index) => *index,
+                None => self.add_node(validator.clone(), indices),
+            })
+            .collect();
+        let inner_quorum_sets = quorum_set
+            .inner_quorum_sets
+            .iter()
+            .map(|inner_quorum_set| self.index_quorum_set(inner_quorum_set, indices))
+            .collect();
+        IndexedQuorumSet {
+            threshold: quorum_set.threshold as usize,
+            validators,
+            inner_quorum_sets,
+        }
+    }
+
+    pub(crate) fn number_of_nodes(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// The node's base64 encoded public key.
+    pub(crate) fn id(&self, node: usize) -> &str {
+        &self.ids[node]
+    }
+
+    fn all_nodes(&self) -> NodeSet {
+        (0..self.number_of_nodes()).collect()
+    }
+
+    /// The nodes whose QSets are known and not empty, i.e. all but the validators that only
+    /// appear in QSets and the nodes without a QSet.
+    pub(crate) fn nodes_with_quorum_sets(&self) -> NodeSet {
+        (0..self.number_of_nodes())
+            .filter(|node| {
+                self.quorum_sets[*node]
+                    .as_ref()
+                    .map_or(false, |quorum_set| quorum_set.threshold > 0)
+            })
+            .collect()
+    }
+
+    /// The largest quorum made up of `nodes`, empty if they don't contain a quorum.
+    /// The `wildcards` are treated as part of every quorum and as always satisfied, i.e. as nodes
+    /// that might lie about their QSets.
+    fn largest_quorum(&self, nodes: &NodeSet, wildcards: &NodeSet) -> NodeSet {
+        let mut quorum = nodes.clone();
+        loop {
+            let present: NodeSet = quorum.union(wildcards).cloned().collect();
+            let unsatisfied: Vec<usize> = quorum
+                .iter()
+                .filter(|node| !self.is_satisfied(**node, &present))
+                .cloned()
+                .collect();
+            if unsatisfied.is_empty() {
+                return quorum;
+            }
+            for node in unsatisfied {
+                quorum.remove(&node);
+            }
+        }
+    }
+
+    fn is_satisfied(&self, node: usize, present: &NodeSet) -> bool {
+        self.quorum_sets[node]
+            .as_ref()
+            .map_or(false, |quorum_set| quorum_set.is_satisfied(present))
+    }
+
+    fn is_quorum(&self, nodes: &NodeSet, wildcards: &NodeSet) -> bool {
+        !nodes.is_empty() && self.largest_quorum(nodes, wildcards).len() == nodes.len()
+    }
+
+    pub(crate) fn minimal_quorums(&self) -> Vec<NodeSet> {
+        self.minimal_quorums_with(&NodeSet::new())
+    }
+
+    /// The minimal quorums when the `wildcards` may lie about their QSets, the wildcards
+    /// themselves are left out of the quorums.
+    fn minimal_quorums_with(&self, wildcards: &NodeSet) -> Vec<NodeSet> {
+        let honest: NodeSet = self.all_nodes().difference(wildcards).cloned().collect();
+        let candidates: Vec<usize> = self
+            .largest_quorum(&honest, wildcards)
+            .into_iter()
+            .collect();
+        let mut found = vec![];
+        self.find_minimal_quorums(NodeSet::new(), &candidates, wildcards, &mut found);
+        found
+    }
+
+    /// Branches on whether the first of the `available` nodes is added to the `selection`.
+    fn find_minimal_quorums(
+        &self,
+        selection: NodeSet,
+        available: &[usize],
+        wildcards: &NodeSet,
+        found: &mut Vec<NodeSet>,
+    ) {
+        if self.is_quorum(&selection, wildcards) {
+            let is_minimal = selection.iter().all(|node| {
+                let mut smaller = selection.clone();
+                smaller.remove(node);
+                self.largest_quorum(&smaller, wildcards).is_empty()
+            });
+            if is_minimal {
+                found.push(selection);
+            }
+            return;
+        }
+        let (next, rest) = if let Some(split) = available.split_first() {
+            split
+        } else {
+            return;
+        };
+        // Give up on the selection if it can't be extended to a quorum with the available nodes
+        let mut reachable = selection.clone();
+        reachable.extend(available);
+        let largest_quorum = self.largest_quorum(&reachable, wildcards);
+        if largest_quorum.is_empty() || !selection.is_subset(&largest_quorum) {
+            return;
+        }
+        let mut extended = selection.clone();
+        extended.insert(*next);
+        self.find_minimal_quorums(extended, rest, wildcards, found);
+        self.find_minimal_quorums(selection, rest, wildcards, found);
+    }
+
+    /// Every set that intersects all minimal quorums leaves the remaining nodes without a quorum
+    /// when it fails, the minimal ones are the minimal blocking sets.
+    pub(crate) fn minimal_blocking_sets(&self, minimal_quorums: &[NodeSet]) -> Vec<NodeSet> {
+        let mut found = vec![];
+        find_hitting_sets(NodeSet::new(), minimal_quorums, &mut found);
+        minimise(found)
+    }
+
+    /// The minimal sets of nodes that can cause two quorums to intersect only in nodes of the set
+    /// if they lie about their QSets.
+    /// Only nodes with a QSet are candidates. They are checked by increasing size, so the search
+    /// is exponential in the number of these nodes and meant for networks of MobileCoin's size.
+    pub(crate) fn minimal_splitting_sets(&self, minimal_quorums: &[NodeSet]) -> Vec<NodeSet> {
+        if !has_quorum_intersection(minimal_quorums) {
+            return vec![NodeSet::new()];
+        }
+        let nodes: Vec<usize> = self.nodes_with_quorum_sets().into_iter().collect();
+        let mut found: Vec<NodeSet> = vec![];
+        for size in 1..=nodes.len() {
+            for candidate in combinations(&nodes, size) {
+                if found
+                    .iter()
+                    .any(|splitting| splitting.is_subset(&candidate))
+                {
+                    continue;
+                }
+                if !has_quorum_intersection(&self.minimal_quorums_with(&candidate)) {
+                    found.push(candidate);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// The FBAS enjoys quorum intersection if all of its minimal quorums intersect.
+pub(crate) fn has_quorum_intersection(minimal_quorums: &[NodeSet]) -> bool {
+    minimal_quorums.iter().enumerate().all(|(i, quorum)| {
+        minimal_quorums[i + 1..]
+            .iter()
+            .all(|other| !quorum.is_disjoint(other))
+    })
+}
+
+/// Branches on the members of the first set the `selection` doesn't intersect yet.
+fn find_hitting_sets(selection: NodeSet, sets: &[NodeSet], found: &mut Vec<NodeSet>) {
+    if found.iter().any(|hitting| hitting.is_subset(&selection)) {
+        return;
+    }
+    match sets.iter().find(|set| set.is_disjoint(&selection)) {
+        Some(missed) => {
+            for node in missed {
+                let mut extended = selection.clone();
+                extended.insert(*node);
+                find_hitting_sets(extended, sets, found);
+            }
+        }
+        None => found.push(selection),
+    }
+}
+
+/// Sorts the sets and removes duplicates and all sets that contain another set.
+pub(crate) fn minimise<T: Ord>(mut sets: Vec<BTreeSet<T>>) -> Vec<BTreeSet<T>> {
+    sets.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    sets.dedup();
+    let mut minimal: Vec<BTreeSet<T>> = vec![];
+    for set in sets {
+        if !minimal.iter().any(|smaller| smaller.is_subset(&set)) {
+            minimal.push(set);
+        }
+    }
+    minimal
+}
+
+/// The subsets of `nodes` with `size` members in lexicographic order, see combinations.
+struct Combinations<'a> {
+    nodes: &'a [usize],
+    /// The positions in `nodes` of the next subset's members, None once all subsets were returned
+    positions: Option<Vec<usize>>,
+}
+
+impl Iterator for Combinations<'_> {
+    type Item = NodeSet;
+
+    fn next(&mut self) -> Option<NodeSet> {
+        let positions = self.positions.as_mut()?;
+        let combination = positions
+            .iter()
+            .map(|position| self.nodes[*position])
+            .collect();
+        // Move the last member that isn't at its final position yet and put the following ones
+        // right behind it
+        let (n, k) = (self.nodes.len(), positions.len());
+        match (0..k).rev().find(|i| positions[*i] < n - k + i) {
+            Some(i) => {
+                positions[i] += 1;
+                for j in i + 1..k {
+                    positions[j] = positions[j - 1] + 1;
+                }
+            }
+            None => self.positions = None,
+        }
+        Some(combination)
+    }
+}
+
+/// All subsets of `nodes` with `size` members, created one at a time since there are
+/// exponentially many of them.
+fn combinations(nodes: &[usize], size: usize) -> Combinations<'_> {
+    Combinations {
+        nodes,
+        positions: (size <= nodes.len()).then(|| (0..size).collect()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::crawl::test_utils::test_key;
+    use crate::io::MobcoinNode;
+    use mc_consensus_scp::test_utils::test_node_id;
+
+    /// Node `i` requires `threshold` of `validators` for every `(threshold, validators)`.
+    pub(crate) fn test_fbas(nodes: &[(u64, &[u32])]) -> MobcoinFbas {
+        MobcoinFbas::new(
+            nodes
+                .iter()
+                .enumerate()
+                .map(|(i, (threshold, validators))| MobcoinNode {
+                    public_key: test_node_id(i as u32).public_key,
+                    hostname: format!("node{}.test.com", i),
+                    port: 443,
+                    active: true,
+                    quorum_set: QuorumSet {
+                        threshold: *threshold,
+                        validators: validators
+                            .iter()
+                            .map(|validator| test_key(*validator))
+                            .collect(),
+                        inner_quorum_sets: vec![],
+                    },
+                    ..MobcoinNode::default()
+                })
+                .collect(),
+        )
+    }
+
+    fn sets(sets: &[&[usize]]) -> Vec<NodeSet> {
+        sets.iter()
+            .map(|set| set.iter().cloned().collect())
+            .collect()
+    }
+
+    #[test]
+    fn symmetric_three_nodes() {
+        let all: &[u32] = &[0, 1, 2];
+        let fbas = Fbas::from_mobcoin_fbas(&test_fbas(&[(2, all), (2, all), (2, all)]));
+        let minimal_quorums = fbas.minimal_quorums();
+        assert_eq!(
+            minimise(minimal_quorums.clone()),
+            sets(&[&[0, 1], &[0, 2], &[1, 2]])
+        );
+        assert!(has_quorum_intersection(&minimal_quorums));
+        assert_eq!(
+            fbas.minimal_blocking_sets(&minimal_quorums),
+            sets(&[&[0, 1], &[0, 2], &[1, 2]])
+        );
+        assert_eq!(
+            fbas.minimal_splitting_sets(&minimal_quorums),
+            sets(&[&[0], &[1], &[2]])
+        );
+    }
+
+    #[test]
+    fn symmetric_four_nodes_tolerate_one_failure() {
+        let all: &[u32] = &[0, 1, 2, 3];
+        let fbas = Fbas::from_mobcoin_fbas(&test_fbas(&[(3, all), (3, all), (3, all), (3, all)]));
+        let minimal_quorums = fbas.minimal_quorums();
+        assert_eq!(minimal_quorums.len(), 4);
+        assert!(minimal_quorums.iter().all(|quorum| quorum.len() == 3));
+        let blocking_sets = fbas.minimal_blocking_sets(&minimal_quorums);
+        assert_eq!(blocking_sets.len(), 6);
+        assert!(blocking_sets.iter().all(|set| set.len() == 2));
+        let splitting_sets = fbas.minimal_splitting_sets(&minimal_quorums);
+        assert_eq!(splitting_sets.len(), 6);
+        assert!(splitting_sets.iter().all(|set| set.len() == 2));
+    }
+
+    #[test]
+    fn disjoint_quorums_are_split_by_the_empty_set() {
+        let fbas = Fbas::from_mobcoin_fbas(&test_fbas(&[
+            (2, &[0, 1]),
+            (2, &[0, 1]),
+            (2, &[2, 3]),
+            (2, &[2, 3]),
+        ]));
+        let minimal_quorums = fbas.minimal_quorums();
+        assert_eq!(minimise(minimal_quorums.clone()), sets(&[&[0, 1], &[2, 3]]));
+        assert!(!has_quorum_intersection(&minimal_quorums));
+        assert_eq!(
+            fbas.minimal_splitting_sets(&minimal_quorums),
+            vec![NodeSet::new()]
+        );
+    }
+
+    #[test]
+    fn unknown_validators_and_empty_quorum_sets_are_never_satisfied() {
+        // Node 3 was never crawled, node 2 has no QSet
+        let fbas = Fbas::from_mobcoin_fbas(&test_fbas(&[(2, &[0, 1]), (2, &[1, 3]), (0, &[])]));
+        assert_eq!(fbas.number_of_nodes(), 4);
+        assert_eq!(fbas.id(3), test_key(3));
+        assert_eq!(fbas.nodes_with_quorum_sets(), NodeSet::from([0, 1]));
+        let minimal_quorums = fbas.minimal_quorums();
+        assert!(minimal_quorums.is_empty());
+        assert!(has_quorum_intersection(&minimal_quorums));
+        // Without a quorum the network is blocked already
+        assert_eq!(
+            fbas.minimal_blocking_sets(&minimal_quorums),
+            vec![NodeSet::new()]
+        );
+    }
+
+    #[test]
+    fn combinations_are_created_in_order() {
+        let nodes = [3, 5, 7, 9];
+        let actual: Vec<NodeSet> = combinations(&nodes, 2).collect();
+        assert_eq!(
+            actual,
+            sets(&[&[3, 5], &[3, 7], &[3, 9], &[5, 7], &[5, 9], &[7, 9]])
+        );
+        assert_eq!(
+            combinations(&nodes, 0).collect::<Vec<_>>(),
+            vec![NodeSet::new()]
+        );
+        assert_eq!(combinations(&nodes, 4).count(), 1);
+        assert_eq!(combinations(&nodes, 5).count(), 0);
+        // Far too many to keep in memory at once
+        assert_eq!(
+            combinations(&(0..64).collect::<Vec<usize>>(), 32).nth(1),
+            Some((0..31).chain([32]).collect())
+        );
+    }
+
+    #[test]
+    fn inner_quorum_sets_are_respected() {
+        let mut mobcoin_fbas = test_fbas(&[(2, &[0]), (2, &[1]), (1, &[2])])
+            .nodes()
+            .to_vec();
+        // Nodes 0 and 1 require themselves and one of the other two nodes
+        for (node, others) in [(0, [1, 2]), (1, [0, 2])] {
+            mobcoin_fbas[node].quorum_set.inner_quorum_sets = vec![QuorumSet {
+                threshold: 1,
+                validators: others.iter().map(|other| test_key(*other)).collect(),
+                inner_quorum_sets: vec![],
+            }];
+        }
+        let fbas = Fbas::from_mobcoin_fbas(&MobcoinFbas::new(mobcoin_fbas));
+        assert_eq!(minimise(fbas.minimal_quorums()), sets(&[&[2], &[0, 1]]));
+    }
+}
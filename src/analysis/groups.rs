@@ -0,0 +1,151 @@
+use crate::io::{GeoData, MobcoinFbas, Organisations};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::collections::{BTreeSet, HashMap};
+
+/// Assigns nodes, given by their base64 encoded public keys, to groups such as organisations,
+/// ISPs or countries. Nodes without a group count as a group of their own.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Groupings(HashMap<String, String>);
+
+impl Groupings {
+    pub fn by_organisation(organisations: &Organisations) -> Self {
+        Self(
+            organisations
+                .organisations()
+                .iter()
+                .flat_map(|organisation| {
+                    organisation
+                        .validators
+                        .iter()
+                        .map(move |validator| (validator.clone(), organisation.name.clone()))
+                })
+                .collect(),
+        )
+    }
+
+    /// Nodes are grouped by their autonomous system if its number is known, e.g. "AS8075", as ISP
+    /// names aren't consistent across database releases.
+    pub fn by_isp(fbas: &MobcoinFbas) -> Self {
+        Self::by_address_attribute(fbas, |isp, geo_data| {
+            match geo_data.autonomous_system_number {
+                Some(asn) => format!("AS{}", asn),
+                None => isp.to_string(),
+            }
+        })
+    }
+
+    pub fn by_country(fbas: &MobcoinFbas) -> Self {
+        Self::by_address_attribute(fbas, |_, geo_data| geo_data.country_name.clone())
+    }
+
+    /// The `attribute` of a node's ISP and GeoData, or those of each of its addresses. Multi-homed
+    /// nodes whose addresses differ in the attribute form a group of their own, e.g.
+    /// "AS16509+AS8075", as losing one of them doesn't take the node down.
+    /// Nodes for which `attribute` is empty, e.g. because they couldn't be geolocated, are left
+    /// without a group.
+    fn by_address_attribute(
+        fbas: &MobcoinFbas,
+        attribute: impl Fn(&str, &GeoData) -> String,
+    ) -> Self {
+        Self(
+            fbas.nodes()
+                .iter()
+                .map(|node| {
+                    let groups: BTreeSet<String> = if node.addresses.is_empty() {
+                        [attribute(node.isp.as_str(), &node.geo_data)]
+                            .into_iter()
+                            .collect()
+                    } else {
+                        node.addresses
+                            .iter()
+                            .map(|address| attribute(address.isp.as_str(), &address.geo_data))
+                            .collect()
+                    };
+                    let group = groups
+                        .into_iter()
+                        .filter(|group| !group.is_empty())
+                        .collect::<Vec<String>>()
+                        .join("+");
+                    (STANDARD.encode(node.public_key), group)
+                })
+                .filter(|(_, group)| !group.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The name of the node's group or the node itself if it doesn't belong to one.
+    pub fn group_of<'a>(&'a self, node: &'a str) -> &'a str {
+        self.0.get(node).map_or(node, |group| group.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::fbas::tests::test_fbas;
+    use crate::crawl::test_utils::test_key;
+    use crate::io::{Organisation, ResolvedAddress};
+
+    #[test]
+    fn nodes_are_grouped_by_attribute() {
+        let all: &[u32] = &[0, 1, 2];
+        let mut nodes = test_fbas(&[(2, all), (2, all), (2, all)]).nodes().to_vec();
+        nodes[0].isp = String::from("ISP A");
+        nodes[1].isp = String::from("ISP A");
+        nodes[1].geo_data.autonomous_system_number = Some(8075);
+        nodes[0].geo_data.country_name = String::from("Germany");
+        let fbas = MobcoinFbas::new(nodes);
+        let by_isp = Groupings::by_isp(&fbas);
+        assert_eq!(by_isp.group_of(&test_key(0)), "ISP A");
+        // The AS number takes precedence
+        assert_eq!(by_isp.group_of(&test_key(1)), "AS8075");
+        // Nodes without an ISP stay on their own
+        assert_eq!(by_isp.group_of(&test_key(2)), test_key(2));
+        let by_country = Groupings::by_country(&fbas);
+        assert_eq!(by_country.group_of(&test_key(0)), "Germany");
+        assert_eq!(by_country.group_of(&test_key(1)), test_key(1));
+        assert!(Groupings::by_isp(&test_fbas(&[(1, &[0])])).is_empty());
+    }
+
+    #[test]
+    fn multi_homed_nodes_form_their_own_group() {
+        let address = |isp: &str, country: &str| ResolvedAddress {
+            isp: isp.to_string(),
+            geo_data: GeoData {
+                country_name: country.to_string(),
+                ..GeoData::default()
+            },
+            ..ResolvedAddress::default()
+        };
+        let all: &[u32] = &[0, 1];
+        let mut nodes = test_fbas(&[(2, all), (2, all)]).nodes().to_vec();
+        nodes[0].addresses = vec![address("ISP B", "Germany"), address("ISP A", "Germany")];
+        nodes[1].addresses = vec![address("ISP A", "Germany"), address("", "")];
+        let fbas = MobcoinFbas::new(nodes);
+        let by_isp = Groupings::by_isp(&fbas);
+        assert_eq!(by_isp.group_of(&test_key(0)), "ISP A+ISP B");
+        // Addresses that couldn't be geolocated don't count
+        assert_eq!(by_isp.group_of(&test_key(1)), "ISP A");
+        let by_country = Groupings::by_country(&fbas);
+        assert_eq!(by_country.group_of(&test_key(0)), "Germany");
+        assert_eq!(by_country.group_of(&test_key(1)), "Germany");
+    }
+
+    #[test]
+    fn nodes_are_grouped_by_organisation() {
+        let organisations = Organisations::new(vec![Organisation {
+            name: String::from("Org"),
+            validators: vec![test_key(0), test_key(1)],
+            ..Organisation::default()
+        }]);
+        let groupings = Groupings::by_organisation(&organisations);
+        assert_eq!(groupings.group_of(&test_key(0)), "Org");
+        assert_eq!(groupings.group_of(&test_key(1)), "Org");
+        assert_eq!(groupings.group_of(&test_key(2)), test_key(2));
+    }
+}
@@ -0,0 +1,6 @@
+mod fbas;
+mod groups;
+mod results;
+
+pub use groups::*;
+pub use results::*;
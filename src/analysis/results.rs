@@ -0,0 +1,345 @@
+use crate::analysis::fbas::*;
+use crate::analysis::groups::Groupings;
+use crate::io::{MobcoinFbas, Organisations};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Splitting sets aren't searched for by default if more nodes than this have a QSet, see
+/// AnalysisSummary::new. The search takes minutes already for a few more nodes.
+pub const DEFAULT_MAX_SPLITTING_SET_CANDIDATES: usize = 12;
+
+/// The quorums, blocking sets and splitting sets of a MobcoinFbas.
+/// Every node set is a sorted list of base64 encoded public keys.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FbasAnalysis {
+    /// Whether every two quorums intersect
+    pub has_quorum_intersection: bool,
+    pub minimal_quorums: Vec<Vec<String>>,
+    /// Minimal sets of nodes whose failure leaves the others without a quorum
+    pub minimal_blocking_sets: Vec<Vec<String>>,
+    /// Minimal sets of nodes that can break quorum intersection by lying about their QSets
+    pub minimal_splitting_sets: Vec<Vec<String>>,
+    /// Set if there were too many nodes to search for splitting sets, minimal_splitting_sets is
+    /// then empty
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub splitting_sets_skipped: bool,
+}
+
+/// The key figures of an FbasAnalysis which are recorded in every CrawlReport.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisSummary {
+    pub has_quorum_intersection: bool,
+    pub minimal_quorums: usize,
+    pub smallest_blocking_set_size: usize,
+    /// None if the network can't be split at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smallest_splitting_set_size: Option<usize>,
+    /// Only present if organisations were given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by_organisation: Option<GroupSummary>,
+    /// Only present if the nodes' ISPs are known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by_isp: Option<GroupSummary>,
+    /// Only present if the nodes' countries are known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by_country: Option<GroupSummary>,
+    /// Set if there were too many nodes to search for splitting sets, the smallest splitting set
+    /// sizes are then missing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub splitting_sets_skipped: bool,
+}
+
+/// The smallest blocking and splitting sets when all nodes of a group count as one.
+/// A size of 1 means a single organisation, ISP or country can block or split the network.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupSummary {
+    pub smallest_blocking_set_size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smallest_splitting_set_size: Option<usize>,
+}
+
+impl AnalysisSummary {
+    /// Analyses the FBAS and groups the results by organisation, if `organisations` are given,
+    /// as well as by ISP and country.
+    /// Splitting sets are only searched for if at most DEFAULT_MAX_SPLITTING_SET_CANDIDATES nodes
+    /// have a QSet.
+    pub fn new(fbas: &MobcoinFbas, organisations: Option<&Organisations>) -> Self {
+        Self::with_splitting_set_limit(fbas, organisations, DEFAULT_MAX_SPLITTING_SET_CANDIDATES)
+    }
+
+    /// Same as new but splitting sets are only searched for if at most `max_candidates` nodes
+    /// have a QSet, see FbasAnalysis::analyse_with_splitting_set_limit.
+    pub fn with_splitting_set_limit(
+        fbas: &MobcoinFbas,
+        organisations: Option<&Organisations>,
+        max_candidates: usize,
+    ) -> Self {
+        let analysis = FbasAnalysis::analyse_with_splitting_set_limit(fbas, max_candidates);
+        let grouped = |groupings: Groupings| {
+            if groupings.is_empty() {
+                None
+            } else {
+                Some(analysis.merged_by(&groupings).group_summary())
+            }
+        };
+        Self {
+            by_organisation: organisations
+                .and_then(|organisations| grouped(Groupings::by_organisation(organisations))),
+            by_isp: grouped(Groupings::by_isp(fbas)),
+            by_country: grouped(Groupings::by_country(fbas)),
+            ..analysis.summary()
+        }
+    }
+}
+
+impl FbasAnalysis {
+    pub fn analyse(fbas: &MobcoinFbas) -> Self {
+        Self::analyse_with_splitting_set_limit(fbas, usize::MAX)
+    }
+
+    /// Same as analyse but the exponential search for splitting sets is skipped if more than
+    /// `max_candidates` nodes have a QSet, or always if it is 0. Validators that only appear in
+    /// QSets don't count.
+    pub fn analyse_with_splitting_set_limit(fbas: &MobcoinFbas, max_candidates: usize) -> Self {
+        let fbas = Fbas::from_mobcoin_fbas(fbas);
+        let minimal_quorums = fbas.minimal_quorums();
+        let candidates = fbas.nodes_with_quorum_sets().len();
+        let splitting_sets_skipped = max_candidates == 0 || candidates > max_candidates;
+        if splitting_sets_skipped {
+            warn!(
+                "Skipping the search for splitting sets among {} nodes, the limit is {}.",
+                candidates, max_candidates
+            );
+        }
+        let to_ids = |sets: Vec<NodeSet>| -> Vec<Vec<String>> {
+            let mut ids: Vec<Vec<String>> = sets
+                .into_iter()
+                .map(|set| {
+                    let mut ids: Vec<String> = set
+                        .into_iter()
+                        .map(|node| fbas.id(node).to_string())
+                        .collect();
+                    ids.sort();
+                    ids
+                })
+                .collect();
+            ids.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+            ids
+        };
+        Self {
+            has_quorum_intersection: has_quorum_intersection(&minimal_quorums),
+            minimal_blocking_sets: to_ids(fbas.minimal_blocking_sets(&minimal_quorums)),
+            minimal_splitting_sets: if splitting_sets_skipped {
+                vec![]
+            } else {
+                to_ids(fbas.minimal_splitting_sets(&minimal_quorums))
+            },
+            minimal_quorums: to_ids(minimal_quorums),
+            splitting_sets_skipped,
+        }
+    }
+
+    /// The key figures without grouping, see AnalysisSummary::new.
+    pub fn summary(&self) -> AnalysisSummary {
+        AnalysisSummary {
+            has_quorum_intersection: self.has_quorum_intersection,
+            minimal_quorums: self.minimal_quorums.len(),
+            smallest_blocking_set_size: smallest(&self.minimal_blocking_sets).unwrap_or_default(),
+            smallest_splitting_set_size: smallest(&self.minimal_splitting_sets),
+            splitting_sets_skipped: self.splitting_sets_skipped,
+            ..AnalysisSummary::default()
+        }
+    }
+
+    /// Replaces the nodes in every set by their groups, the sets then contain group names
+    /// instead of public keys. Sets that are no longer minimal are dropped.
+    pub fn merged_by(&self, groupings: &Groupings) -> Self {
+        let merge = |sets: &[Vec<String>]| -> Vec<Vec<String>> {
+            let merged: Vec<BTreeSet<String>> = sets
+                .iter()
+                .map(|set| {
+                    set.iter()
+                        .map(|node| groupings.group_of(node).to_string())
+                        .collect()
+                })
+                .collect();
+            minimise(merged)
+                .into_iter()
+                .map(|set| set.into_iter().collect())
+                .collect()
+        };
+        Self {
+            has_quorum_intersection: self.has_quorum_intersection,
+            minimal_quorums: merge(&self.minimal_quorums),
+            minimal_blocking_sets: merge(&self.minimal_blocking_sets),
+            minimal_splitting_sets: merge(&self.minimal_splitting_sets),
+            splitting_sets_skipped: self.splitting_sets_skipped,
+        }
+    }
+
+    fn group_summary(&self) -> GroupSummary {
+        GroupSummary {
+            smallest_blocking_set_size: smallest(&self.minimal_blocking_sets).unwrap_or_default(),
+            smallest_splitting_set_size: smallest(&self.minimal_splitting_sets),
+        }
+    }
+}
+
+fn smallest(sets: &[Vec<String>]) -> Option<usize> {
+    sets.iter().map(|set| set.len()).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::fbas::tests::test_fbas;
+    use crate::crawl::test_utils::test_key;
+    use crate::io::Organisation;
+    use std::path::Path;
+
+    #[test]
+    fn analyse_symmetric_network() {
+        let all: &[u32] = &[0, 1, 2];
+        let actual = FbasAnalysis::analyse(&test_fbas(&[(2, all), (2, all), (2, all)]));
+        let mut pair = vec![test_key(0), test_key(1)];
+        pair.sort();
+        assert!(actual.has_quorum_intersection);
+        assert_eq!(actual.minimal_quorums.len(), 3);
+        assert!(actual.minimal_quorums.contains(&pair));
+        assert!(actual.minimal_blocking_sets.contains(&pair));
+        assert_eq!(actual.minimal_splitting_sets[0].len(), 1);
+        assert_eq!(
+            actual.summary(),
+            AnalysisSummary {
+                has_quorum_intersection: true,
+                minimal_quorums: 3,
+                smallest_blocking_set_size: 2,
+                smallest_splitting_set_size: Some(1),
+                ..AnalysisSummary::default()
+            }
+        );
+    }
+
+    #[test]
+    fn single_node_cannot_be_split() {
+        let actual = FbasAnalysis::analyse(&test_fbas(&[(1, &[0])]));
+        assert_eq!(actual.minimal_quorums, vec![vec![test_key(0)]]);
+        assert!(actual.minimal_splitting_sets.is_empty());
+        assert_eq!(actual.summary().smallest_splitting_set_size, None);
+        assert_eq!(actual.summary().smallest_blocking_set_size, 1);
+    }
+
+    #[test]
+    fn analyse_historical_network() {
+        let fbas = MobcoinFbas::from_file(Path::new(
+            "crawl_data/mobilecoin_nodes_completed_manually_2021-08-02.json",
+        ))
+        .unwrap();
+        // All 10 validators require 8 of the 10
+        let actual = FbasAnalysis::analyse(&fbas).summary();
+        assert_eq!(
+            actual,
+            AnalysisSummary {
+                has_quorum_intersection: true,
+                minimal_quorums: 45,
+                smallest_blocking_set_size: 3,
+                smallest_splitting_set_size: Some(6),
+                ..AnalysisSummary::default()
+            }
+        );
+    }
+
+    #[test]
+    fn merged_sets_stay_minimal() {
+        let all: &[u32] = &[0, 1, 2, 3];
+        let analysis = FbasAnalysis::analyse(&test_fbas(&[(3, all), (3, all), (3, all), (3, all)]));
+        let organisations = Organisations::new(vec![Organisation {
+            name: String::from("Org"),
+            validators: vec![test_key(0), test_key(1)],
+            ..Organisation::default()
+        }]);
+        let actual = analysis.merged_by(&Groupings::by_organisation(&organisations));
+        let mut others = vec![test_key(2), test_key(3)];
+        others.sort();
+        // {0, 1} becomes {Org} which makes every set containing 0 or 1 redundant
+        assert_eq!(
+            actual.minimal_blocking_sets,
+            vec![vec![String::from("Org")], others.clone()]
+        );
+        assert_eq!(actual.minimal_splitting_sets, actual.minimal_blocking_sets);
+        let with_org = |node| {
+            let mut set = vec![String::from("Org"), test_key(node)];
+            set.sort();
+            set
+        };
+        assert_eq!(actual.minimal_quorums.len(), 2);
+        assert!(actual.minimal_quorums.contains(&with_org(2)));
+        assert!(actual.minimal_quorums.contains(&with_org(3)));
+    }
+
+    #[test]
+    fn historical_network_by_group() {
+        let fbas = MobcoinFbas::from_file(Path::new(
+            "crawl_data/mobilecoin_nodes_completed_manually_2021-08-02.json",
+        ))
+        .unwrap();
+        let organisations = Organisations::from_file(Path::new(
+            "crawl_data/mobilecoin_organisations_2021-08-02_created_manually.json",
+        ))
+        .unwrap();
+        let actual = AnalysisSummary::new(&fbas, Some(&organisations));
+        // MobileCoin Worldwide runs 3 of the 10 validators, so it can block the network on its own
+        assert_eq!(
+            actual.by_organisation,
+            Some(GroupSummary {
+                smallest_blocking_set_size: 1,
+                smallest_splitting_set_size: Some(3),
+            })
+        );
+        // 9 validators are hosted by Microsoft and 7 are in the Netherlands
+        let single = Some(GroupSummary {
+            smallest_blocking_set_size: 1,
+            smallest_splitting_set_size: Some(1),
+        });
+        assert_eq!(actual.by_isp, single);
+        assert_eq!(actual.by_country, single);
+        assert_eq!(AnalysisSummary::new(&fbas, None).by_organisation, None);
+    }
+
+    #[test]
+    fn splitting_sets_of_large_networks_are_skipped() {
+        let all: &[u32] = &[0, 1, 2];
+        // Node 3 only appears in QSets and isn't counted
+        let fbas = test_fbas(&[(2, all), (2, all), (2, &[0, 1, 2, 3])]);
+        let actual = AnalysisSummary::with_splitting_set_limit(&fbas, None, 2);
+        assert_eq!(
+            actual,
+            AnalysisSummary {
+                has_quorum_intersection: true,
+                minimal_quorums: 3,
+                smallest_blocking_set_size: 2,
+                smallest_splitting_set_size: None,
+                splitting_sets_skipped: true,
+                ..AnalysisSummary::default()
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&actual).unwrap()["splittingSetsSkipped"],
+            true
+        );
+        let actual = AnalysisSummary::with_splitting_set_limit(&fbas, None, 3);
+        assert!(!actual.splitting_sets_skipped);
+        assert_eq!(actual.smallest_splitting_set_size, Some(1));
+        assert!(serde_json::to_value(&actual)
+            .unwrap()
+            .get("splittingSetsSkipped")
+            .is_none());
+        // 0 skips the search even without any candidates
+        let empty = FbasAnalysis::analyse_with_splitting_set_limit(&MobcoinFbas::default(), 0);
+        assert!(empty.splitting_sets_skipped);
+    }
+}
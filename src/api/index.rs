@@ -0,0 +1,172 @@
+use crate::error::Error;
+use crate::io::REPORT_FILE_PREFIX;
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A crawl report written to the output directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedReport {
+    /// The timestamp as it appears in the file name
+    pub timestamp: String,
+    pub path: PathBuf,
+}
+
+/// The crawl reports in an output directory, ordered by their timestamps.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReportIndex {
+    reports: BTreeMap<DateTime<Utc>, IndexedReport>,
+}
+
+impl ReportIndex {
+    /// Indexes all "mobilecoin_crawl_report_<RFC3339 timestamp>.json" files in `dir`.
+    /// Files whose timestamp can't be parsed are skipped.
+    pub fn scan(dir: &Path) -> Result<Self, Error> {
+        let entries =
+            fs::read_dir(dir).map_err(|err| Error::Io(format!("{}: {}", dir.display(), err)))?;
+        let mut reports = BTreeMap::new();
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            let timestamp = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(REPORT_FILE_PREFIX))
+                .and_then(|name| name.strip_suffix(".json"));
+            let timestamp = if let Some(timestamp) = timestamp {
+                timestamp.to_string()
+            } else {
+                continue;
+            };
+            match parse_timestamp(&timestamp) {
+                Ok(time) => {
+                    reports.insert(time, IndexedReport { timestamp, path });
+                }
+                Err(err) => debug!("Skipping {:?}: {}", path, err),
+            }
+        }
+        Ok(ReportIndex { reports })
+    }
+
+    pub fn len(&self) -> usize {
+        self.reports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// The most recent crawl.
+    pub fn latest(&self) -> Option<&IndexedReport> {
+        self.reports.values().next_back()
+    }
+
+    /// The crawl recorded at `time` or, if there is none, the closest one before it.
+    pub fn at_or_before(&self, time: DateTime<Utc>) -> Option<&IndexedReport> {
+        self.reports
+            .range(..=time)
+            .next_back()
+            .map(|(_, report)| report)
+    }
+
+    /// All crawls recorded between `from` and `to` (both inclusive), oldest first.
+    /// A missing bound leaves the range open on that side.
+    pub fn between(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Vec<&IndexedReport> {
+        self.reports
+            .iter()
+            .filter(|(time, _)| from.map_or(true, |from| **time >= from))
+            .filter(|(time, _)| to.map_or(true, |to| **time <= to))
+            .map(|(_, report)| report)
+            .collect()
+    }
+}
+
+/// Parses an RFC 3339 timestamp such as "2021-08-23T20:00:00.007Z".
+pub fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|err| Error::Timestamp(format!("{}: {}", timestamp, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_reports(dir: &Path, timestamps: &[&str]) {
+        for timestamp in timestamps {
+            let name = format!("{}{}.json", REPORT_FILE_PREFIX, timestamp);
+            fs::write(
+                dir.join(name),
+                format!("{{\"timestamp\":\"{}\"}}", timestamp),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn only_reports_are_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+        write_reports(dir.path(), &["2021-08-23T20:00:00.007Z", "not-a-timestamp"]);
+        fs::write(
+            dir.path()
+                .join("mobilecoin_nodes_2021-08-23T20:00:00.007Z.json"),
+            "[]",
+        )
+        .unwrap();
+        let index = ReportIndex::scan(dir.path()).unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index.latest().unwrap().timestamp,
+            "2021-08-23T20:00:00.007Z"
+        );
+    }
+
+    #[test]
+    fn nearest_earlier_crawl_is_found() {
+        let dir = tempfile::tempdir().unwrap();
+        write_reports(
+            dir.path(),
+            &[
+                "2021-08-23T20:00:00+00:00",
+                "2021-08-23T21:00:00+00:00",
+                "2021-08-23T22:00:00+00:00",
+            ],
+        );
+        let index = ReportIndex::scan(dir.path()).unwrap();
+        let at = |timestamp| {
+            index
+                .at_or_before(parse_timestamp(timestamp).unwrap())
+                .map(|report| report.timestamp.clone())
+        };
+        assert_eq!(
+            at("2021-08-23T21:00:00Z"),
+            Some(String::from("2021-08-23T21:00:00+00:00"))
+        );
+        // Offsets are taken into account
+        assert_eq!(
+            at("2021-08-23T23:59:00+02:00"),
+            Some(String::from("2021-08-23T21:00:00+00:00"))
+        );
+        assert_eq!(
+            at("2030-01-01T00:00:00Z"),
+            Some(String::from("2021-08-23T22:00:00+00:00"))
+        );
+        assert_eq!(at("2021-08-23T19:59:59Z"), None);
+        let between = index.between(Some(parse_timestamp("2021-08-23T20:30:00Z").unwrap()), None);
+        assert_eq!(between.len(), 2);
+        assert_eq!(between[0].timestamp, "2021-08-23T21:00:00+00:00");
+    }
+
+    #[test]
+    fn missing_dir_is_an_error() {
+        let actual = ReportIndex::scan(Path::new("./does-not-exist"));
+        assert!(matches!(actual, Err(Error::Io(_))));
+    }
+}
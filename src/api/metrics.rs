@@ -0,0 +1,173 @@
+use crate::io::{CrawlReport, MobcoinNode};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fmt::Write;
+
+/// The content type of the Prometheus text exposition format.
+pub static METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Renders a crawl report in the Prometheus text format.
+///
+/// Network wide gauges come from the report's NodeInfo and duration, per node gauges are
+/// labelled with the node's public key, hostname and port.
+pub fn render_metrics(report: &CrawlReport) -> String {
+    let node_info = &report.node_info;
+    let nodes = report.nodes.nodes();
+    let mut metrics = String::new();
+    gauge(
+        &mut metrics,
+        "mobilecoin_nodes",
+        "Number of nodes found in the latest crawl.",
+        &[("", Some(node_info.total_nodes as f64))],
+    );
+    gauge(
+        &mut metrics,
+        "mobilecoin_nodes_reachable",
+        "Number of nodes that answered in the latest crawl.",
+        &[("", Some(node_info.reachable_nodes as f64))],
+    );
+    gauge(
+        &mut metrics,
+        "mobilecoin_highest_slot_index",
+        "Highest slot any node reported working on in the latest crawl.",
+        &[("", node_info.highest_slot_index.map(|slot| slot as f64))],
+    );
+    gauge(
+        &mut metrics,
+        "mobilecoin_crawl_duration_seconds",
+        "How long the latest crawl took.",
+        &[("", Some(report.duration.as_secs_f64()))],
+    );
+    let labels: Vec<String> = nodes.iter().map(node_labels).collect();
+    let per_node = |value: &dyn Fn(&MobcoinNode) -> Option<f64>| -> Vec<(&str, Option<f64>)> {
+        labels
+            .iter()
+            .zip(nodes)
+            .map(|(labels, node)| (labels.as_str(), value(node)))
+            .collect()
+    };
+    gauge(
+        &mut metrics,
+        "mobilecoin_node_active",
+        "Whether the node answered in the latest crawl.",
+        &per_node(&|node| Some(if node.active { 1.0 } else { 0.0 })),
+    );
+    gauge(
+        &mut metrics,
+        "mobilecoin_node_rpc_latency_seconds",
+        "How long the node took to answer the crawler's RPC.",
+        &per_node(&|node| node.rpc_latency.map(|latency| latency.as_secs_f64())),
+    );
+    gauge(
+        &mut metrics,
+        "mobilecoin_node_rpc_attempts",
+        "How many RPCs were sent to the node in the latest crawl.",
+        &per_node(&|node| Some(f64::from(node.rpc_attempts))),
+    );
+    metrics
+}
+
+/// Appends a gauge with one sample per set of labels, samples without a value are left out.
+fn gauge(metrics: &mut String, name: &str, help: &str, samples: &[(&str, Option<f64>)]) {
+    let _ = writeln!(metrics, "# HELP {} {}", name, help);
+    let _ = writeln!(metrics, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        if let Some(value) = value {
+            let _ = writeln!(metrics, "{}{} {}", name, labels, value);
+        }
+    }
+}
+
+fn node_labels(node: &MobcoinNode) -> String {
+    format!(
+        "{{public_key=\"{}\",hostname=\"{}\",port=\"{}\"}}",
+        STANDARD.encode(node.public_key),
+        escape_label(&node.hostname),
+        node.port
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{MobcoinFbas, NodeInfo};
+    use mc_consensus_scp::test_utils::test_node_id;
+    use std::time::Duration;
+
+    fn report() -> CrawlReport {
+        let nodes = vec![
+            MobcoinNode {
+                public_key: test_node_id(0).public_key,
+                hostname: String::from("node0.test.com"),
+                port: 443,
+                active: true,
+                rpc_attempts: 1,
+                rpc_latency: Some(Duration::from_millis(25)),
+                ..MobcoinNode::default()
+            },
+            MobcoinNode {
+                public_key: test_node_id(1).public_key,
+                hostname: String::from("node\"1"),
+                port: 443,
+                active: false,
+                rpc_attempts: 3,
+                ..MobcoinNode::default()
+            },
+        ];
+        CrawlReport {
+            timestamp: String::from("2021-08-23T20:00:00+00:00"),
+            duration: Duration::from_millis(2500),
+            node_info: NodeInfo {
+                total_nodes: 2,
+                reachable_nodes: 1,
+                highest_slot_index: Some(42),
+            },
+            analysis: None,
+            geo_ip_databases: vec![],
+            nodes: MobcoinFbas::new(nodes),
+        }
+    }
+
+    #[test]
+    fn report_to_metrics() {
+        let actual = render_metrics(&report());
+        let node_0 = STANDARD.encode(test_node_id(0).public_key);
+        let node_1 = STANDARD.encode(test_node_id(1).public_key);
+        assert!(actual.contains("# TYPE mobilecoin_nodes gauge\nmobilecoin_nodes 2\n"));
+        assert!(actual.contains("mobilecoin_nodes_reachable 1\n"));
+        assert!(actual.contains("mobilecoin_highest_slot_index 42\n"));
+        assert!(actual.contains("mobilecoin_crawl_duration_seconds 2.5\n"));
+        assert!(actual.contains(&format!(
+            "mobilecoin_node_active{{public_key=\"{}\",hostname=\"node0.test.com\",port=\"443\"}} 1\n",
+            node_0
+        )));
+        assert!(actual.contains(&format!(
+            "mobilecoin_node_active{{public_key=\"{}\",hostname=\"node\\\"1\",port=\"443\"}} 0\n",
+            node_1
+        )));
+        assert!(actual.contains(&format!(
+            "mobilecoin_node_rpc_latency_seconds{{public_key=\"{}\"",
+            node_0
+        )));
+        // Unreachable nodes have no latency
+        assert!(!actual.contains(&format!(
+            "mobilecoin_node_rpc_latency_seconds{{public_key=\"{}\"",
+            node_1
+        )));
+    }
+
+    #[test]
+    fn report_without_slot_index() {
+        let mut report = report();
+        report.node_info.highest_slot_index = None;
+        let actual = render_metrics(&report);
+        assert!(actual.contains("# TYPE mobilecoin_highest_slot_index gauge\n# HELP"));
+    }
+}
@@ -0,0 +1,7 @@
+mod index;
+mod metrics;
+mod server;
+
+pub use index::*;
+pub use metrics::*;
+pub use server::*;
@@ -0,0 +1,360 @@
+use crate::api::index::{parse_timestamp, IndexedReport, ReportIndex};
+use crate::api::metrics::{render_metrics, METRICS_CONTENT_TYPE};
+use crate::error::Error;
+use crate::io::CrawlReport;
+
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tiny_http::{Header, Method, Response, Server};
+use url::Url;
+
+/// The status and JSON body the API answers a request with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Serves the crawl reports in an output directory over HTTP.
+///
+/// - `/v1` returns the most recent crawl
+/// - `/v1/<RFC3339 timestamp>` returns the crawl recorded at the timestamp or the closest one
+///   before it
+/// - `/v1/crawls?from=<timestamp>&to=<timestamp>` lists the timestamps of all crawls in the
+///   (optional) range
+/// - `/v1/range?from=<timestamp>&to=<timestamp>` returns all crawls in the (optional) range
+/// - `/metrics` returns gauges about the most recent crawl in the Prometheus text format
+///
+/// The directory is indexed again for every request so that reports written by a running daemon
+/// are served right away.
+pub struct ApiServer {
+    server: Server,
+    dir: PathBuf,
+}
+
+impl ApiResponse {
+    fn ok(body: String) -> Self {
+        ApiResponse {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: u16, msg: &str) -> Self {
+        ApiResponse {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": msg }).to_string(),
+        }
+    }
+}
+
+impl ApiServer {
+    /// Listens on `addr`, e.g. "127.0.0.1:8080".
+    pub fn bind(addr: &str, dir: PathBuf) -> Result<Self, Error> {
+        let server = Server::http(addr).map_err(|err| Error::Http(format!("{}: {}", addr, err)))?;
+        Ok(ApiServer { server, dir })
+    }
+
+    /// The address the server ended up listening on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Answers requests until `shutdown` is set.
+    pub fn run(&self, shutdown: &AtomicBool) {
+        while !shutdown.load(Ordering::Relaxed) {
+            let request = match self.server.recv_timeout(Duration::from_millis(200)) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!("Error receiving request: {}", err);
+                    continue;
+                }
+            };
+            let response = if *request.method() == Method::Get {
+                self.handle(request.url())
+            } else {
+                ApiResponse::error(405, "only GET requests are supported")
+            };
+            debug!(
+                "{} {} -> {}",
+                request.method(),
+                request.url(),
+                response.status
+            );
+            let content_type =
+                Header::from_bytes(&b"Content-Type"[..], response.content_type.as_bytes())
+                    .expect("Static header is valid");
+            let http_response = Response::from_string(response.body)
+                .with_status_code(response.status)
+                .with_header(content_type);
+            if let Err(err) = request.respond(http_response) {
+                warn!("Error sending response: {}", err);
+            }
+        }
+    }
+
+    /// Routes a request for `url`, which consists of the path and the query.
+    pub fn handle(&self, url: &str) -> ApiResponse {
+        handle_request(&self.dir, url)
+    }
+}
+
+/// Answers a request for `url` using the reports in `dir`.
+pub fn handle_request(dir: &Path, url: &str) -> ApiResponse {
+    let url = match Url::parse(&format!("http://localhost{}", url)) {
+        Ok(url) => url,
+        Err(err) => return ApiResponse::error(400, &err.to_string()),
+    };
+    let index = match ReportIndex::scan(dir) {
+        Ok(index) => index,
+        Err(err) => {
+            warn!("{}", err);
+            return ApiResponse::error(500, "crawl reports unavailable");
+        }
+    };
+    let segments: Vec<&str> = url
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match segments.as_slice() {
+        ["v1"] => match index.latest() {
+            Some(report) => read_report(report),
+            None => ApiResponse::error(404, "no crawls recorded"),
+        },
+        ["v1", "crawls"] => match query_range(&url) {
+            Ok((from, to)) => {
+                let timestamps: Vec<&str> = index
+                    .between(from, to)
+                    .into_iter()
+                    .map(|report| report.timestamp.as_str())
+                    .collect();
+                ApiResponse::ok(serde_json::json!(timestamps).to_string())
+            }
+            Err(err) => ApiResponse::error(400, &err.to_string()),
+        },
+        ["v1", "range"] => match query_range(&url) {
+            Ok((from, to)) => {
+                let mut reports = vec![];
+                for report in index.between(from, to) {
+                    let response = read_report(report);
+                    if response.status != 200 {
+                        return response;
+                    }
+                    reports.push(response.body);
+                }
+                ApiResponse::ok(format!("[{}]", reports.join(",")))
+            }
+            Err(err) => ApiResponse::error(400, &err.to_string()),
+        },
+        ["metrics"] => match index.latest() {
+            Some(report) => match CrawlReport::from_file(&report.path) {
+                Ok(report) => ApiResponse {
+                    status: 200,
+                    content_type: METRICS_CONTENT_TYPE,
+                    body: render_metrics(&report),
+                },
+                Err(err) => {
+                    warn!("{}", err);
+                    ApiResponse::error(500, "latest crawl report unreadable")
+                }
+            },
+            None => ApiResponse::error(404, "no crawls recorded"),
+        },
+        ["v1", timestamp] => match parse_timestamp(&percent_decode(timestamp)) {
+            Ok(time) => match index.at_or_before(time) {
+                Some(report) => read_report(report),
+                None => ApiResponse::error(404, "no crawl recorded before this timestamp"),
+            },
+            Err(err) => ApiResponse::error(400, &err.to_string()),
+        },
+        _ => ApiResponse::error(404, "not found"),
+    }
+}
+
+fn read_report(report: &IndexedReport) -> ApiResponse {
+    match fs::read_to_string(&report.path) {
+        Ok(body) => ApiResponse::ok(body),
+        Err(err) => {
+            warn!("Error reading {:?}: {}", report.path, err);
+            ApiResponse::error(500, "crawl report unavailable")
+        }
+    }
+}
+
+/// The optional "from" and "to" query parameters.
+fn query_range(url: &Url) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), Error> {
+    let mut from = None;
+    let mut to = None;
+    for (key, value) in url.query_pairs() {
+        // An unencoded "+" in an offset is decoded to a space
+        let value = value.replace(' ', "+");
+        match key.as_ref() {
+            "from" => from = Some(parse_timestamp(&value)?),
+            "to" => to = Some(parse_timestamp(&value)?),
+            _ => {}
+        }
+    }
+    Ok((from, to))
+}
+
+/// Decodes the %XX escapes in a path segment, e.g. "%3A" for ":" and "%2B" for "+".
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        if let Some(byte) = escaped {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{MobcoinFbas, MobcoinNode, NodeInfo, REPORT_FILE_PREFIX};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn report_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for hour in 20..23 {
+            let timestamp = format!("2021-08-23T{}:00:00+00:00", hour);
+            let name = format!("{}{}.json", REPORT_FILE_PREFIX, timestamp);
+            fs::write(
+                dir.path().join(name),
+                format!("{{\"timestamp\":\"{}\"}}", timestamp),
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn latest_and_nearest_earlier_crawls_are_served() {
+        let dir = report_dir();
+        let latest = handle_request(dir.path(), "/v1");
+        assert_eq!(latest.status, 200);
+        assert!(latest.body.contains("22:00:00"));
+        let earlier = handle_request(dir.path(), "/v1/2021-08-23T21:30:00.000Z");
+        assert_eq!(earlier.status, 200);
+        assert!(earlier.body.contains("21:00:00"));
+        let encoded = handle_request(dir.path(), "/v1/2021-08-23T23%3A30%3A00%2B02%3A00");
+        assert!(encoded.body.contains("21:00:00"));
+        assert_eq!(
+            handle_request(dir.path(), "/v1/2021-08-23T19:00:00Z").status,
+            404
+        );
+        assert_eq!(handle_request(dir.path(), "/v1/yesterday").status, 400);
+        assert_eq!(handle_request(dir.path(), "/v2").status, 404);
+    }
+
+    #[test]
+    fn crawls_are_listed_and_ranged() {
+        let dir = report_dir();
+        let all = handle_request(dir.path(), "/v1/crawls");
+        let all: Vec<String> = serde_json::from_str(&all.body).unwrap();
+        assert_eq!(all.len(), 3);
+        let range = handle_request(
+            dir.path(),
+            "/v1/range?from=2021-08-23T20:30:00Z&to=2021-08-23T21:00:00+00:00",
+        );
+        assert_eq!(range.status, 200);
+        let range: Vec<serde_json::Value> = serde_json::from_str(&range.body).unwrap();
+        assert_eq!(range.len(), 1);
+        assert_eq!(range[0]["timestamp"], "2021-08-23T21:00:00+00:00");
+        let invalid = handle_request(dir.path(), "/v1/crawls?from=soon");
+        assert_eq!(invalid.status, 400);
+    }
+
+    #[test]
+    fn empty_dir_has_no_latest_crawl() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(handle_request(dir.path(), "/v1").status, 404);
+        assert_eq!(handle_request(dir.path(), "/v1/crawls").body, "[]");
+    }
+
+    /// Starts a server on `dir` and sends it a GET request for `path`.
+    fn http_get(dir: &Path, path: &str) -> String {
+        let server = Arc::new(ApiServer::bind("127.0.0.1:0", dir.to_path_buf()).unwrap());
+        let addr = server.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let server = Arc::clone(&server);
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || server.run(&shutdown))
+        };
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        shutdown.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn server_answers_over_http() {
+        let dir = report_dir();
+        let response = http_get(dir.path(), "/v1");
+        assert!(response.starts_with("HTTP/1.0 200") || response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("application/json"));
+        assert!(response.contains("22:00:00"));
+    }
+
+    #[test]
+    fn metrics_are_scraped_over_http() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = CrawlReport {
+            timestamp: String::from("2021-08-23T20:00:00+00:00"),
+            duration: Duration::from_secs(3),
+            node_info: NodeInfo {
+                total_nodes: 1,
+                reachable_nodes: 1,
+                highest_slot_index: None,
+            },
+            analysis: None,
+            geo_ip_databases: vec![],
+            nodes: MobcoinFbas::new(vec![MobcoinNode {
+                hostname: String::from("node0.test.com"),
+                port: 443,
+                active: true,
+                rpc_attempts: 1,
+                rpc_latency: Some(Duration::from_millis(5)),
+                ..MobcoinNode::default()
+            }]),
+        };
+        let report = serde_json::to_string(&report).unwrap();
+        let name = format!("{}2021-08-23T20:00:00+00:00.json", REPORT_FILE_PREFIX);
+        fs::write(dir.path().join(name), report).unwrap();
+        let response = http_get(dir.path(), "/metrics");
+        assert!(response.contains("200"));
+        assert!(response.contains(METRICS_CONTENT_TYPE));
+        assert!(response.contains("mobilecoin_nodes_reachable 1\n"));
+        assert!(response.contains("mobilecoin_crawl_duration_seconds 3\n"));
+        assert!(response.contains("hostname=\"node0.test.com\",port=\"443\"} 0.005"));
+    }
+}
@@ -1,13 +1,28 @@
 use env_logger::Env;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 use mc_crawler::{
-    crawl,
-    io::{CrawlReport, MobcoinFbas},
+    analysis::DEFAULT_MAX_SPLITTING_SET_CANDIDATES,
+    api::parse_timestamp,
+    api::ApiServer,
+    crawl::{self, CachingResolver, Resolver, StaticResolver, SystemResolver},
+    io::{
+        diff_reports, CrawlReport, MobcoinFbas, OrganisationMapping, Organisations, TrustGraph,
+        GRAPH_FILE_PREFIX, NODES_FILE_PREFIX, ORGANISATIONS_FILE_PREFIX, REPORT_FILE_PREFIX,
+    },
+    stats::{GeoIpConfig, GeoLookup},
+    Error,
 };
 
 /// Crawl the MobileCoin Network and return the results in a JSON that can be passed to other programs
@@ -38,49 +53,277 @@ struct Opt {
     #[structopt(short, long)]
     complete: bool,
 
+    /// Output the trust graph in GraphViz DOT format, with an edge from every node to each
+    /// validator in its QSet, including inner QSets.
+    /// Usage example "cargo run-- --dot"
+    #[structopt(long)]
+    dot: bool,
+
+    /// Output the trust graph in GraphML format, see "--dot".
+    #[structopt(long)]
+    graphml: bool,
+
+    /// Maximum number of nodes that are crawled concurrently.
+    /// Usage example "cargo run-- -w 16"
+    #[structopt(short, long, default_value = "8")]
+    workers: usize,
+
+    /// Seconds to wait for the connection to a node to become ready.
+    #[structopt(long, default_value = "5", parse(try_from_str = parse_seconds))]
+    connect_timeout: Duration,
+
+    /// Seconds a single get_latest_msg RPC may take before it is cancelled.
+    #[structopt(long, default_value = "10", parse(try_from_str = parse_seconds))]
+    rpc_deadline: Duration,
+
+    /// How often a failed RPC is repeated before a node is considered unreachable.
+    #[structopt(long, default_value = "2")]
+    retries: u32,
+
+    /// Seconds to wait before the first retry; the pause doubles with every further retry, up
+    /// to a minute.
+    #[structopt(long, default_value = "0.5", parse(try_from_str = parse_seconds))]
+    retry_backoff: Duration,
+
+    /// Contact nodes found in quorum sets via "insecure-mc://", i.e. without TLS.
+    /// Only useful for local test networks.
+    #[structopt(long)]
+    insecure: bool,
+
+    /// Leave the quorum sets of nodes whose consensus messages aren't correctly signed, or are
+    /// signed with a key their peers don't list for them, out of the FBAS.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Periodically save the crawl state to this file so that the crawl can be resumed with
+    /// "--resume" if it is interrupted.
+    #[structopt(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Minimum number of seconds between two checkpoints.
+    #[structopt(long, default_value = "60", parse(try_from_str = parse_seconds))]
+    checkpoint_interval: Duration,
+
+    /// Continue the crawl saved in this checkpoint file instead of starting from the bootstrap
+    /// nodes. New checkpoints are written to the same file unless "--checkpoint" is passed.
+    #[structopt(long)]
+    resume: Option<PathBuf>,
+
+    /// Keep crawling, starting a new crawl every given number of seconds, until SIGTERM or
+    /// SIGINT is received. The GeoIP databases are only opened once.
+    /// Usage example "cargo run-- --fbas --interval 3600"
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    interval: Option<Duration>,
+
+    /// Serve the HTTP API, including Prometheus metrics under "/metrics", from the output
+    /// directory while crawling. See the serve subcommand for the endpoints.
+    /// Usage example "cargo run-- --complete --interval 3600 --listen 127.0.0.1:8080"
+    #[structopt(long)]
+    listen: Option<String>,
+
+    /// Only keep the output files of the given number of most recent crawls, older ones are
+    /// deleted after each crawl.
+    #[structopt(long)]
+    keep: Option<usize>,
+
+    /// Organisations JSON (see crawl_data/) used to group the analysis in the crawl report by
+    /// organisation. The analysis is always grouped by ISP and country.
+    #[structopt(long)]
+    organisations: Option<PathBuf>,
+
+    /// JSON object mapping hostnames or domains to the names of the organisations running them,
+    /// e.g. {"mobilecoinww.com": "MobileCoin Worldwide"}. Nodes are assigned to the organisation of
+    /// the longest matching entry and otherwise grouped by the registrable domain of their
+    /// hostname.
+    #[structopt(long)]
+    org_mapping: Option<PathBuf>,
+
+    /// Only assign nodes to organisations listed in the "--org-mapping" file.
+    #[structopt(long, requires = "org-mapping")]
+    org_mapping_only: bool,
+
+    /// Skip the search for splitting sets in the crawl report's analysis if more than the given
+    /// number of nodes have a QSet, its cost grows exponentially with the number of nodes. Pass 0
+    /// to always skip it. Defaults to 12.
+    /// Usage example "cargo run-- --complete --max-splitting-set-candidates 16"
+    #[structopt(long)]
+    max_splitting_set_candidates: Option<usize>,
+
+    /// GeoLite2-ASN or GeoIP2-ISP database used to look up the nodes' ISPs.
+    /// Overrides the "--geoip-config" file and the MC_CRAWLER_ISP_DB environment variable, the
+    /// bundled GeoLite2 snapshot is used if none of them is set.
+    #[structopt(long)]
+    isp_db: Option<PathBuf>,
+
+    /// GeoLite2-Country, GeoLite2-City or the GeoIP2 equivalent used to look up the nodes'
+    /// countries. Overrides the "--geoip-config" file and the MC_CRAWLER_COUNTRY_DB environment
+    /// variable.
+    #[structopt(long)]
+    country_db: Option<PathBuf>,
+
+    /// JSON object with the paths to the GeoIP databases, e.g.
+    /// {"ispDb": "/var/lib/GeoIP/GeoIP2-ISP.mmdb", "countryDb": "/var/lib/GeoIP/GeoLite2-City.mmdb"}.
+    /// Takes precedence over the environment variables.
+    #[structopt(long)]
+    geoip_config: Option<PathBuf>,
+
+    /// Hosts file in the format of /etc/hosts whose addresses are used for the listed hostnames
+    /// instead of asking the system's resolver, e.g. to re-create reports offline with fixed IPs.
+    #[structopt(long)]
+    hosts: Option<PathBuf>,
+
     /// Path to text file with the bootstrap nodes as URIs.
     /// Each node expected on a new line while lines beginning with "//" are understood to be
     /// comments and ignored.
     /// Will default to "./bootstrap.txt" if omitted.
     nodes_path: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-fn create_output_dir(path: Option<&PathBuf>) -> Option<String> {
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Serve the crawl reports in the output directory over HTTP.
+    /// "/v1" returns the latest crawl and "/v1/<RFC3339 timestamp>" the closest crawl at or before
+    /// the timestamp. "/v1/crawls" lists the timestamps of all crawls and "/v1/range" returns the
+    /// crawls themselves, both accept optional "from" and "to" timestamps as query parameters.
+    /// Usage example "cargo run-- serve --listen 0.0.0.0:8080"
+    Serve {
+        /// Directory with the crawl reports.
+        /// Defaults to "crawl_data/"
+        #[structopt(short, long)]
+        dir: Option<PathBuf>,
+
+        /// Address the API listens on.
+        #[structopt(short, long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Compare two crawl reports (or nodes JSONs) and list the nodes that appeared or disappeared,
+    /// changed their active status, quorum set, ISP or country.
+    /// Usage example "cargo run -- diff old_report.json new_report.json --json"
+    Diff {
+        /// The older crawl.
+        old: PathBuf,
+
+        /// The newer crawl.
+        new: PathBuf,
+
+        /// Print the changes as JSON instead of text.
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+/// Parses a number of seconds, which may be fractional, into a Duration.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|err| format!("{}: {}", value, err))?;
+    if !seconds.is_finite() || seconds < 0.0 || seconds >= u64::MAX as f64 {
+        return Err(format!(
+            "{}: expected a non-negative, finite number of seconds",
+            value
+        ));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn create_output_dir(path: Option<&PathBuf>) -> Result<String, Error> {
     let path_to_dir = if let Some(dir) = path {
         dir.as_path().display().to_string()
     } else {
         String::from("crawl_data")
     };
-    if fs::create_dir_all(path_to_dir.clone()).is_ok() {
-        Some(path_to_dir)
-    } else {
-        warn!("Error creating output directory..\nWill not create output files.");
-        None
-    }
+    fs::create_dir_all(&path_to_dir)
+        .map_err(|err| Error::Io(format!("{}: {}", path_to_dir, err)))?;
+    Ok(path_to_dir)
 }
 
-fn write_report_to_file(output_dir: Option<String>, timestamp: String, report: CrawlReport) {
-    if let Some(path_to_dir) = output_dir {
-        let file_name = format!(
-            "{}/{}{}{}",
-            path_to_dir, "mobilecoin_crawl_report_", timestamp, ".json"
-        );
-        let file = File::create(file_name.clone()).expect("Error creating file");
-        info!("Writing report to file {}", file_name);
-        serde_json::to_writer_pretty(file, &report).expect("Error while writing report.");
-    };
+/// Writes `value` as pretty printed JSON to the file `file_name`.
+fn write_json_to_file(file_name: &str, value: &impl Serialize) -> Result<(), Error> {
+    let file =
+        File::create(file_name).map_err(|err| Error::Io(format!("{}: {}", file_name, err)))?;
+    serde_json::to_writer_pretty(file, value)
+        .map_err(|err| Error::Io(format!("{}: {}", file_name, err)))
 }
 
-fn write_fbas_to_file(output_dir: Option<String>, timestamp: String, fbas: MobcoinFbas) {
-    if let Some(path_to_dir) = output_dir {
-        let file_name = format!(
-            "{}/{}{}{}",
-            path_to_dir, "mobilecoin_nodes_", timestamp, ".json"
-        );
-        let file = File::create(file_name.clone()).expect("Error creating file");
-        info!("Writing fbas to file {}", file_name);
-        serde_json::to_writer_pretty(file, &fbas).expect("Error while writing report.");
+fn write_report_to_file(
+    path_to_dir: &str,
+    timestamp: &str,
+    report: &CrawlReport,
+) -> Result<(), Error> {
+    let file_name = format!(
+        "{}/{}{}{}",
+        path_to_dir, REPORT_FILE_PREFIX, timestamp, ".json"
+    );
+    info!("Writing report to file {}", file_name);
+    write_json_to_file(&file_name, report)
+}
+
+fn write_fbas_to_file(path_to_dir: &str, timestamp: &str, fbas: &MobcoinFbas) -> Result<(), Error> {
+    let file_name = format!(
+        "{}/{}{}{}",
+        path_to_dir, NODES_FILE_PREFIX, timestamp, ".json"
+    );
+    info!("Writing fbas to file {}", file_name);
+    write_json_to_file(&file_name, fbas)
+}
+
+fn write_graph_to_file(
+    path_to_dir: &str,
+    timestamp: &str,
+    extension: &str,
+    graph: String,
+) -> Result<(), Error> {
+    let file_name = format!(
+        "{}/{}{}.{}",
+        path_to_dir, GRAPH_FILE_PREFIX, timestamp, extension
+    );
+    info!("Writing trust graph to file {}", file_name);
+    fs::write(&file_name, graph).map_err(|err| Error::Io(format!("{}: {}", file_name, err)))
+}
+
+fn write_organisations_to_file(
+    path_to_dir: &str,
+    timestamp: &str,
+    organisations: &Organisations,
+) -> Result<(), Error> {
+    let file_name = format!(
+        "{}/{}{}{}",
+        path_to_dir, ORGANISATIONS_FILE_PREFIX, timestamp, ".json"
+    );
+    info!("Writing organisations to file {}", file_name);
+    write_json_to_file(&file_name, organisations)
+}
+
+/// Deletes all but the `keep` newest files in `dir` whose names start with `prefix` and end in
+/// `extension`. The names end in the crawl's RFC3339 timestamp, so sorting them sorts the crawls.
+/// Files without a timestamp, such as hand-written organisations JSONs, are left alone.
+fn prune_output_files(dir: &str, prefix: &str, extension: &str, keep: usize) {
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix(prefix))
+                    .and_then(|name| name.strip_suffix(extension))
+                    .map_or(false, |timestamp| parse_timestamp(timestamp).is_ok())
+            })
+            .collect(),
+        Err(err) => {
+            warn!("Error listing output directory {}: {}", dir, err);
+            return;
+        }
     };
+    files.sort();
+    let outdated = files.len().saturating_sub(keep);
+    for file in files.into_iter().take(outdated) {
+        debug!("Removing old output file {:?}", file);
+        if let Err(err) = fs::remove_file(&file) {
+            warn!("Error removing {:?}: {}", file, err);
+        }
+    }
 }
 
 fn read_bs_peers(path: Option<&PathBuf>) -> Vec<String> {
@@ -104,6 +347,111 @@ fn read_bs_peers(path: Option<&PathBuf>) -> Vec<String> {
     bs_peers
 }
 
+/// Writes the outputs requested in `args` for a finished crawl.
+/// Stops at the first output that can't be written.
+fn write_output_files(
+    args: &Opt,
+    crawler: &crawl::Crawler,
+    geo_lookup: Option<&GeoLookup>,
+    organisations: Option<&Organisations>,
+    org_mapping: &OrganisationMapping,
+    hosts: Option<&StaticResolver>,
+) -> Result<(), Error> {
+    let geo_lookup = if let Some(geo_lookup) = geo_lookup {
+        geo_lookup
+    } else {
+        return Ok(());
+    };
+    let path_to_dir = create_output_dir(args.output.as_ref())?;
+    let timestamp = crawler.crawl_time.as_str();
+    let mut fbas =
+        MobcoinFbas::create_mobcoin_fbas_with(crawler, geo_lookup, resolver(hosts).as_ref());
+    let generated_organisations = fbas.assign_organisations(org_mapping);
+    if args.fbas {
+        write_fbas_to_file(&path_to_dir, timestamp, &fbas)?;
+        write_organisations_to_file(&path_to_dir, timestamp, &generated_organisations)?;
+    }
+    if args.dot || args.graphml {
+        let graph = TrustGraph::from_mobcoin_fbas(&fbas);
+        if args.dot {
+            write_graph_to_file(&path_to_dir, timestamp, "dot", graph.to_dot())?;
+        }
+        if args.graphml {
+            write_graph_to_file(&path_to_dir, timestamp, "graphml", graph.to_graphml())?;
+        }
+    }
+    if args.complete {
+        let organisations = organisations.unwrap_or(&generated_organisations);
+        let report = CrawlReport::create_crawl_report(
+            fbas,
+            crawler,
+            Some(organisations),
+            args.max_splitting_set_candidates
+                .unwrap_or(DEFAULT_MAX_SPLITTING_SET_CANDIDATES),
+            geo_lookup.databases(),
+        );
+        write_report_to_file(&path_to_dir, timestamp, &report)?;
+    }
+    if let Some(keep) = args.keep {
+        prune_output_files(&path_to_dir, NODES_FILE_PREFIX, ".json", keep);
+        prune_output_files(&path_to_dir, REPORT_FILE_PREFIX, ".json", keep);
+        prune_output_files(&path_to_dir, ORGANISATIONS_FILE_PREFIX, ".json", keep);
+        prune_output_files(&path_to_dir, GRAPH_FILE_PREFIX, ".dot", keep);
+        prune_output_files(&path_to_dir, GRAPH_FILE_PREFIX, ".graphml", keep);
+    }
+    Ok(())
+}
+
+/// The `hosts` read from the "--hosts" file, if given, and then the system's resolver.
+/// The system's resolver is created anew for every crawl, so the daemon notices changed DNS
+/// records.
+fn resolver(hosts: Option<&StaticResolver>) -> Box<dyn Resolver> {
+    let system = CachingResolver::new(SystemResolver);
+    match hosts {
+        Some(hosts) => Box::new(hosts.clone().with_fallback(system)),
+        None => Box::new(system),
+    }
+}
+
+/// The GeoIP database paths from the command line, "--geoip-config" or the environment, in this
+/// order of precedence.
+fn geo_ip_config(args: &Opt) -> GeoIpConfig {
+    let config = match args.geoip_config.as_ref() {
+        Some(path) => GeoIpConfig::from_file(path).expect("Error reading GeoIP config."),
+        None => GeoIpConfig::from_env(),
+    };
+    GeoIpConfig {
+        isp_db: args.isp_db.clone().unwrap_or(config.isp_db),
+        country_db: args.country_db.clone().unwrap_or(config.country_db),
+    }
+}
+
+/// A flag that is set once SIGTERM or SIGINT is received; a second signal terminates the process
+/// right away.
+fn shutdown_flag() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))
+            .expect("Error registering signal handler.");
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))
+            .expect("Error registering signal handler.");
+    }
+    shutdown
+}
+
+/// Sleeps until `next_crawl` unless a shutdown is requested in the meantime.
+/// Returns whether the daemon should keep going.
+fn wait_for_next_crawl(next_crawl: Instant, shutdown: &AtomicBool) -> bool {
+    while !shutdown.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= next_crawl {
+            return true;
+        }
+        thread::sleep((next_crawl - now).min(Duration::from_millis(200)));
+    }
+    false
+}
+
 pub fn main() {
     let args = Opt::from_args();
     let log_level = if args.debug { "debug" } else { "info" };
@@ -112,20 +460,120 @@ pub fn main() {
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
-    let bs_peers = read_bs_peers(args.nodes_path.as_ref());
-    let mut crawler = crawl::Crawler::new(bs_peers);
-    crawler.crawl_network();
-    if args.fbas || args.complete {
-        let output_dir = create_output_dir(args.output.as_ref());
-        if output_dir.is_some() {
-            let fbas = MobcoinFbas::create_mobcoin_fbas(&crawler);
-            if args.fbas {
-                write_fbas_to_file(output_dir.clone(), crawler.crawl_time.clone(), fbas.clone());
-            }
-            if args.complete {
-                let report = CrawlReport::create_crawl_report(fbas, &crawler);
-                write_report_to_file(output_dir, crawler.crawl_time, report);
+    if let Some(Command::Serve { dir, listen }) = args.cmd.as_ref() {
+        let dir = dir.clone().unwrap_or_else(|| PathBuf::from("crawl_data"));
+        let server = ApiServer::bind(listen, dir.clone()).expect("Error starting HTTP server.");
+        info!("Serving crawl reports from {:?} on {}", dir, listen);
+        server.run(&shutdown_flag());
+        info!("Shutting down..");
+        return;
+    }
+    if let Some(Command::Diff { old, new, json }) = args.cmd.as_ref() {
+        let old = CrawlReport::from_file(old).expect("Error reading old crawl.");
+        let new = CrawlReport::from_file(new).expect("Error reading new crawl.");
+        let diff = diff_reports(&old, &new);
+        if *json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&diff).expect("Error serialising diff.")
+            );
+        } else {
+            print!("{}", diff);
+        }
+        return;
+    }
+
+    let config = crawl::CrawlConfig {
+        workers: args.workers,
+        connect_timeout: args.connect_timeout,
+        rpc_deadline: args.rpc_deadline,
+        max_retries: args.retries,
+        retry_backoff: args.retry_backoff,
+        insecure: args.insecure,
+        strict: args.strict,
+        checkpoint: args.checkpoint.clone().or_else(|| args.resume.clone()),
+        checkpoint_interval: args.checkpoint_interval,
+    };
+    // Crawls that are in progress are finished and written before the daemon exits
+    let shutdown = if args.interval.is_some() {
+        shutdown_flag()
+    } else {
+        Arc::new(AtomicBool::new(false))
+    };
+    if let Some(listen) = args.listen.clone() {
+        let dir = args
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("crawl_data"));
+        let server = ApiServer::bind(&listen, dir).expect("Error starting HTTP server.");
+        info!("Serving crawl reports on {}", listen);
+        let shutdown = Arc::clone(&shutdown);
+        thread::spawn(move || server.run(&shutdown));
+    }
+    let geo_lookup = if args.fbas || args.complete || args.dot || args.graphml {
+        let geo_lookup =
+            GeoLookup::open_with(&geo_ip_config(&args)).expect("Error opening GeoIP databases.");
+        for db in geo_lookup.databases() {
+            info!(
+                "Using {} database built at epoch {}",
+                db.database_type, db.build_epoch
+            );
+        }
+        Some(geo_lookup)
+    } else {
+        None
+    };
+    let organisations = args
+        .organisations
+        .as_ref()
+        .map(|path| Organisations::from_file(path).expect("Error reading organisations file."));
+    let org_mapping = match args.org_mapping.as_ref() {
+        Some(path) => OrganisationMapping::from_file(path, !args.org_mapping_only)
+            .expect("Error reading organisation mapping."),
+        None => OrganisationMapping::by_domain(),
+    };
+    let hosts = args
+        .hosts
+        .as_ref()
+        .map(|path| StaticResolver::from_file(path).expect("Error reading hosts file."));
+    let bs_peers = if args.resume.is_some() && args.interval.is_none() {
+        vec![]
+    } else {
+        read_bs_peers(args.nodes_path.as_ref())
+    };
+    let mut crawler = if let Some(checkpoint) = args.resume.as_ref() {
+        info!("Resuming crawl from {:?}", checkpoint);
+        crawl::Crawler::load_checkpoint(checkpoint).expect("Error loading checkpoint.")
+    } else {
+        crawl::Crawler::new(bs_peers.clone())
+    };
+    loop {
+        let started = Instant::now();
+        crawler = crawler.with_config(config.clone());
+        crawler.crawl_network();
+        if let Err(err) = write_output_files(
+            &args,
+            &crawler,
+            geo_lookup.as_ref(),
+            organisations.as_ref(),
+            &org_mapping,
+            hosts.as_ref(),
+        ) {
+            error!("Error writing output files: {}", err);
+            // The daemon tries again after the next crawl
+            if args.interval.is_none() {
+                process::exit(1);
             }
         }
+        let interval = if let Some(interval) = args.interval {
+            interval
+        } else {
+            break;
+        };
+        if !wait_for_next_crawl(started + interval, &shutdown) {
+            info!("Shutting down..");
+            break;
+        }
+        crawler = crawl::Crawler::new(bs_peers.clone());
     }
 }
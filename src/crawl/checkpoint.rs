@@ -0,0 +1,167 @@
+use crate::crawl::core_types::*;
+use crate::error::Error;
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Keeps track of how long a (possibly resumed) crawl has been running and when its state was
+/// last written to the checkpoint file.
+pub(crate) struct CheckpointTimer {
+    started: Instant,
+    /// How long the crawl ran before it was resumed
+    duration_before: Duration,
+    last_saved: Instant,
+}
+
+impl CheckpointTimer {
+    pub(crate) fn new(duration_before: Duration) -> Self {
+        let now = Instant::now();
+        CheckpointTimer {
+            started: now,
+            duration_before,
+            last_saved: now,
+        }
+    }
+
+    /// The total time spent crawling, including the time before the crawl was resumed.
+    pub(crate) fn crawl_duration(&self) -> Duration {
+        self.duration_before + self.started.elapsed()
+    }
+}
+
+impl Crawler {
+    /// Writes the crawl state, i.e. the queue, the crawled peers, the discovered nodes and the
+    /// timing, to `path` as JSON.
+    /// The file is replaced atomically so a crawl killed while writing leaves the previous
+    /// checkpoint intact.
+    pub fn save_checkpoint(&self, path: &Path) -> Result<(), Error> {
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path)
+            .map_err(|err| Error::Io(format!("{}: {}", tmp_path.display(), err)))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|err| Error::Io(format!("{}: {}", tmp_path.display(), err)))?;
+        writer
+            .flush()
+            .map_err(|err| Error::Io(format!("{}: {}", tmp_path.display(), err)))?;
+        fs::rename(&tmp_path, path).map_err(|err| Error::Io(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Restores a crawl from a checkpoint written by save_checkpoint.
+    /// The config isn't part of the checkpoint, use with_config to set it again.
+    pub fn load_checkpoint(path: &Path) -> Result<Self, Error> {
+        let file =
+            File::open(path).map_err(|err| Error::Io(format!("{}: {}", path.display(), err)))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| Error::Deserialise(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Saves a checkpoint if one is configured and either `force` is set or the checkpoint
+    /// interval has passed since the last one.
+    /// A failed checkpoint is logged but doesn't stop the crawl.
+    pub(crate) fn checkpoint(&mut self, timer: &mut CheckpointTimer, force: bool) {
+        let path = if let Some(path) = self.config.checkpoint.clone() {
+            path
+        } else {
+            return;
+        };
+        if !force && timer.last_saved.elapsed() < self.config.checkpoint_interval {
+            return;
+        }
+        self.crawl_duration = timer.crawl_duration();
+        match self.save_checkpoint(&path) {
+            Ok(()) => debug!("Saved checkpoint to {}.", path.display()),
+            Err(err) => warn!("Couldn't save checkpoint: {}", err),
+        }
+        timer.last_saved = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::test_utils::{test_consensus_msg, test_payload, test_quorum_set, test_uri};
+    use crate::crawl::transport::MemoryTransport;
+    use mc_consensus_scp::test_utils::test_node_id;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn crawled_node(node: u32) -> CrawledNode {
+        let mut crawled =
+            CrawledNode::new(test_uri(node), true, test_quorum_set(&[node, node + 1])).unwrap();
+        crawled.public_key = test_node_id(node).public_key;
+        crawled.rpc_attempts = 1;
+        crawled.latest_msg = Some(ScpStatement::from_consensus_msg(&test_consensus_msg(
+            test_node_id(node),
+            test_quorum_set(&[node, node + 1]),
+        )));
+        crawled.signature_status = SignatureStatus::Invalid;
+        crawled
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let mut crawler = Crawler::new(vec![test_uri(1)]);
+        crawler
+            .crawled
+            .insert(NodeAddress::from_str(&test_uri(0)).unwrap());
+        crawler.mobcoin_nodes.insert(crawled_node(0));
+        crawler.reachable_nodes = 1;
+        crawler.crawl_duration = Duration::from_millis(1500);
+        crawler.crawl_time = String::from("2022-05-01T12:00:00+00:00");
+        crawler.save_checkpoint(&path).unwrap();
+        let actual = Crawler::load_checkpoint(&path).unwrap();
+        assert_eq!(actual, crawler);
+    }
+
+    #[test]
+    fn loading_missing_or_corrupt_checkpoint_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        assert!(matches!(Crawler::load_checkpoint(&path), Err(Error::Io(_))));
+        fs::write(&path, "{\"to_crawl\": [").unwrap();
+        assert!(matches!(
+            Crawler::load_checkpoint(&path),
+            Err(Error::Deserialise(_))
+        ));
+    }
+
+    #[test]
+    fn resumed_crawl_skips_crawled_peers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let mut interrupted = Crawler::new(vec![test_uri(1)]);
+        interrupted
+            .crawled
+            .insert(NodeAddress::from_str(&test_uri(0)).unwrap());
+        interrupted.mobcoin_nodes.insert(crawled_node(0));
+        interrupted.reachable_nodes = 1;
+        interrupted.crawl_duration = Duration::from_secs(60);
+        interrupted.save_checkpoint(&path).unwrap();
+
+        let transport = Arc::new(
+            MemoryTransport::default()
+                .with_payload(&test_uri(0), test_payload(0, &[0, 1]))
+                .with_payload(&test_uri(1), test_payload(1, &[0, 1])),
+        );
+        let config = CrawlConfig {
+            checkpoint: Some(path.clone()),
+            ..CrawlConfig::default()
+        };
+        let mut crawler = Crawler::load_checkpoint(&path).unwrap().with_config(config);
+        crawler.crawl_network_with(transport.clone());
+        assert_eq!(transport.requests(&test_uri(0)), 0);
+        assert_eq!(transport.requests(&test_uri(1)), 1);
+        assert_eq!(crawler.mobcoin_nodes.len(), 2);
+        assert_eq!(crawler.reachable_nodes, 2);
+        assert!(crawler.crawl_duration >= Duration::from_secs(60));
+        // The finished crawl is checkpointed as well
+        let saved = Crawler::load_checkpoint(&path).unwrap();
+        assert!(saved.to_crawl.is_empty());
+        assert_eq!(saved.mobcoin_nodes, crawler.mobcoin_nodes);
+    }
+}
@@ -1,90 +1,337 @@
+use crate::crawl::resolver::Resolver;
+use crate::error::Error;
+
 use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 use url::Url;
 
-use mc_consensus_scp::QuorumSet as McQuorumSet;
+use mc_common::{NodeID, ResponderId};
+use mc_consensus_scp::{msg::Topic, QuorumSet as McQuorumSet};
 use mc_crypto_keys::Ed25519Public;
+use mc_peers::ConsensusMsg;
+use mc_util_uri::{ConnectionUri, ConsensusClientUri as ClientUri};
+use serde::{Deserialize, Serialize};
+
+/// The normalised URI of a peer. Two URIs that lead to the same host and port, e.g. ones that only
+/// differ in casing, a trailing slash or an omitted default port, give the same NodeAddress.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NodeAddress {
+    /// Whether the peer is contacted over TLS, i.e. "mc://" rather than "insecure-mc://"
+    tls: bool,
+    host: String,
+    port: u16,
+}
 
 /// A CrawledNode is a MobileCoin network node that we have learned of during the crawl. The
 /// Crawler keeps a tally of these during a crawl and each will later be transformed to a MobCoinNode.
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
 pub(crate) struct CrawledNode {
     pub(crate) public_key: Ed25519Public,
     pub(crate) domain: String,
     pub(crate) port: u16,
     pub(crate) quorum_set: McQuorumSet,
     pub(crate) online: bool,
+    /// How many RPCs were sent before the node replied or was given up on
+    pub(crate) rpc_attempts: u32,
+    /// How long the RPC the node answered took
+    pub(crate) rpc_latency: Option<Duration>,
+    /// The node's latest SCP message, if it sent one
+    pub(crate) latest_msg: Option<ScpStatement>,
+    /// Whether the latest message was signed by its sender
+    pub(crate) signature_status: SignatureStatus,
+    /// Keys other nodes list for this node's responder ID that differ from its own
+    pub(crate) conflicting_keys: Vec<Ed25519Public>,
+}
+
+/// The outcome of checking a node's ConsensusMsg signature against the sender's public key.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SignatureStatus {
+    /// The signature matches the sender's public key
+    Verified,
+    /// There was no message whose signature could be checked
+    Unverified,
+    /// The signature does not match the sender's public key
+    Invalid,
+    /// The signature matches the sender's public key, but the node's peers only list it under
+    /// other keys
+    Mismatched,
+}
+
+/// The SCP phase a node's latest message belongs to.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScpPhase {
+    Nominate,
+    NominatePrepare,
+    Prepare,
+    Commit,
+    Externalize,
+}
+
+/// Everything but the values of the last SCP message a node sent.
+/// Which of the ballot counters are set depends on the phase.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+pub struct ScpStatement {
+    /// The node that sent (and signed) the message
+    pub sender_id: NodeID,
+    /// The slot, i.e. block index, the node is working on
+    pub slot_index: u64,
+    pub phase: ScpPhase,
+    /// The counter of the current ballot (`b` while preparing/committing, `c` once externalized)
+    pub ballot_counter: Option<u32>,
+    /// The counter of the highest accepted prepared ballot (`p`)
+    pub prepared_counter: Option<u32>,
+    /// The counter of the highest accepted prepared ballot incompatible with `p` (`p'`)
+    pub prepared_prime_counter: Option<u32>,
+    /// The lowest ballot counter the node votes to commit (`c.N`)
+    pub commit_counter: Option<u32>,
+    /// The highest ballot counter the node votes to commit or has confirmed prepared (`h.N`)
+    pub high_counter: Option<u32>,
+    /// The signature over the message and the previous block ID
+    pub signature: Vec<u8>,
+}
+
+/// The number of peers crawled concurrently unless configured otherwise.
+pub const DEFAULT_WORKERS: usize = 8;
+
+/// Settings that control how the crawler talks to each peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrawlConfig {
+    /// The maximum number of peers that are crawled concurrently
+    pub workers: usize,
+    /// How long to wait for the connection to a peer to become ready
+    pub connect_timeout: Duration,
+    /// How long a single get_latest_msg call may take
+    pub rpc_deadline: Duration,
+    /// How often a failed RPC is repeated before the peer is marked as unreachable
+    pub max_retries: u32,
+    /// The pause before the first retry, doubled for every further retry up to a minute
+    pub retry_backoff: Duration,
+    /// Contact discovered peers without TLS, e.g. on local test networks
+    pub insecure: bool,
+    /// Leave the QSets of nodes whose messages could not be verified out of the FBAS, they are
+    /// still used to discover peers
+    pub strict: bool,
+    /// Where the crawl state is written to so that an interrupted crawl can be resumed
+    pub checkpoint: Option<PathBuf>,
+    /// The minimum time between two checkpoints
+    pub checkpoint_interval: Duration,
 }
 
 /// The Crawler object steers a crawl.
-#[derive(Clone, Debug, PartialEq, Eq, Default)]
+/// Everything but the config is saved in checkpoints, see Crawler::save_checkpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Crawler {
     /// A HashSet of discovered nodes
     pub(crate) mobcoin_nodes: HashSet<CrawledNode>,
     /// A HashSet of nodes to be crawled
-    pub(crate) to_crawl: HashSet<String>,
+    pub(crate) to_crawl: HashSet<NodeAddress>,
     /// A HashSet of nodes that have been crawled
-    pub crawled: HashSet<String>,
-    /// The number of nodes the crawler got a response from
+    pub crawled: HashSet<NodeAddress>,
+    /// The number of nodes the crawler got a response from.
+    /// Counts addresses while crawling and nodes once they have been merged by their PK.
     pub(crate) reachable_nodes: usize,
     /// How long the crawl took
     pub(crate) crawl_duration: Duration,
     /// The crawl's timestamp
     pub crawl_time: String,
+    /// How peers are contacted
+    #[serde(skip)]
+    pub(crate) config: CrawlConfig,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        CrawlConfig {
+            workers: DEFAULT_WORKERS,
+            connect_timeout: Duration::from_secs(5),
+            rpc_deadline: Duration::from_secs(10),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(500),
+            insecure: false,
+            strict: false,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+        }
+    }
 }
 
 impl CrawledNode {
     /// Create a new CrawledNode using its hostname, connectivity status and Qset.
-    pub(crate) fn new(url: String, online: bool, quorum_set: McQuorumSet) -> Self {
-        let (domain, port) = Self::fragment_mc_url(url);
-        CrawledNode {
+    pub(crate) fn new(url: String, online: bool, quorum_set: McQuorumSet) -> Result<Self, Error> {
+        let (domain, port) = Self::fragment_mc_url(url)?;
+        Ok(CrawledNode {
             public_key: Ed25519Public::default(),
             domain,
             port,
             quorum_set,
             online,
-        }
+            rpc_attempts: 0,
+            rpc_latency: None,
+            latest_msg: None,
+            signature_status: SignatureStatus::Unverified,
+            conflicting_keys: vec![],
+        })
     }
 
-    /// Return 0.0.0.0 as an address if not resolvable otherwise the stats functions would return one own's geolocation
-    fn fragment_mc_url(url: String) -> (String, u16) {
-        let url = Url::parse(&url).expect("Failed to parse into Url");
-        let domain = url.domain();
-        let port = url.port();
+    /// Splits the URL into host and port, IPv6 hosts keep their brackets, e.g. "[::1]".
+    /// URLs without either are an error rather than a placeholder address, which would be
+    /// geolocated.
+    fn fragment_mc_url(url: String) -> Result<(String, u16), Error> {
+        let parsed = Url::parse(&url).map_err(|err| Error::Uri(format!("{}: {}", url, err)))?;
+        match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) if !host.is_empty() => Ok((host.to_string(), port)),
+            _ => Err(Error::Uri(format!("{}: missing host or port", url))),
+        }
+    }
 
-        let (ip, port_nr) = if domain.is_none() || port.is_none() {
-            (IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)).to_string(), 0)
-        } else if let Some(host) = domain {
-            (String::from(host), port.unwrap_or(0))
+    /// Returns all IPv4 and IPv6 addresses the `resolver` finds for the node's hostname, sorted
+    /// and without duplicates. Fails if there are none.
+    pub fn resolve_hostname(&self, resolver: &dyn Resolver) -> Result<Vec<IpAddr>, Error> {
+        let mut ips = resolver.resolve(&self.domain, self.port)?;
+        ips.sort();
+        ips.dedup();
+        if ips.is_empty() {
+            Err(Error::Dns(format!(
+                "{}:{}: no addresses",
+                self.domain, self.port
+            )))
         } else {
-            (IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)).to_string(), 0)
-        };
-        (ip, port_nr)
+            Ok(ips)
+        }
+    }
+}
+
+impl NodeAddress {
+    /// The address under which a node listed in a QSet is crawled.
+    pub(crate) fn from_responder_id(
+        responder_id: &ResponderId,
+        config: &CrawlConfig,
+    ) -> Result<Self, Error> {
+        Self::from_str(&format!("{}{}", config.peer_scheme(), responder_id))
     }
+}
 
-    pub fn resolve_hostname_to_ip(&self) -> IpAddr {
-        let hostname = format!("{}:{}", self.domain, self.port);
-        let mut addrs = if let Ok(socket) = hostname.to_socket_addrs() {
-            socket
-        } else {
-            warn!("Error resolving {hostname}");
-            Vec::default().into_iter()
+impl FromStr for NodeAddress {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Self, Error> {
+        let parsed = ClientUri::from_str(uri.trim()).map_err(|_| Error::Uri(uri.to_string()))?;
+        let host = parsed.host().trim_end_matches('.').to_lowercase();
+        if host.is_empty() {
+            return Err(Error::Uri(uri.to_string()));
+        }
+        Ok(NodeAddress {
+            tls: parsed.use_tls(),
+            host,
+            port: parsed.port(),
+        })
+    }
+}
+
+impl TryFrom<String> for NodeAddress {
+    type Error = Error;
+
+    fn try_from(uri: String) -> Result<Self, Error> {
+        Self::from_str(&uri)
+    }
+}
+
+impl From<NodeAddress> for String {
+    fn from(address: NodeAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "mc" } else { "insecure-mc" };
+        write!(f, "{}://{}:{}", scheme, self.host, self.port)
+    }
+}
+
+impl Default for SignatureStatus {
+    fn default() -> Self {
+        SignatureStatus::Unverified
+    }
+}
+
+impl ScpStatement {
+    /// Keeps the metadata of a ConsensusMsg and drops the values it is about.
+    pub(crate) fn from_consensus_msg(msg: &ConsensusMsg) -> Self {
+        let scp_msg = &msg.scp_msg;
+        let phase = match scp_msg.topic {
+            Topic::Nominate(_) => ScpPhase::Nominate,
+            Topic::NominatePrepare(..) => ScpPhase::NominatePrepare,
+            Topic::Prepare(_) => ScpPhase::Prepare,
+            Topic::Commit(_) => ScpPhase::Commit,
+            Topic::Externalize(_) => ScpPhase::Externalize,
         };
-        if let Some(resolved) = addrs.next() {
-            resolved.ip()
+        let mut statement = ScpStatement {
+            sender_id: scp_msg.sender_id.clone(),
+            slot_index: scp_msg.slot_index,
+            phase,
+            ballot_counter: None,
+            prepared_counter: None,
+            prepared_prime_counter: None,
+            commit_counter: None,
+            high_counter: None,
+            signature: msg.signature.as_ref().to_vec(),
+        };
+        match &scp_msg.topic {
+            Topic::Nominate(_) => {}
+            Topic::NominatePrepare(_, prepare) | Topic::Prepare(prepare) => {
+                statement.ballot_counter = Some(prepare.B.N);
+                statement.prepared_counter = prepare.P.as_ref().map(|ballot| ballot.N);
+                statement.prepared_prime_counter = prepare.PP.as_ref().map(|ballot| ballot.N);
+                statement.commit_counter = Some(prepare.CN);
+                statement.high_counter = Some(prepare.HN);
+            }
+            Topic::Commit(commit) => {
+                statement.ballot_counter = Some(commit.B.N);
+                statement.prepared_counter = Some(commit.PN);
+                statement.commit_counter = Some(commit.CN);
+                statement.high_counter = Some(commit.HN);
+            }
+            Topic::Externalize(externalize) => {
+                statement.ballot_counter = Some(externalize.C.N);
+                statement.commit_counter = Some(externalize.C.N);
+                statement.high_counter = Some(externalize.HN);
+            }
+        }
+        statement
+    }
+}
+
+impl CrawlConfig {
+    /// The URI scheme used for peers found in quorum sets.
+    pub(crate) fn peer_scheme(&self) -> &'static str {
+        if self.insecure {
+            "insecure-mc://"
         } else {
-            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+            "mc://"
         }
     }
 }
 
 impl Crawler {
     /// Create a new Crawler and add bootstrap peers.
+    /// Peers whose URI can't be parsed are skipped.
     pub fn new(bootstrap_peers: Vec<String>) -> Self {
-        let mut to_crawl: HashSet<String> = HashSet::new();
+        let mut to_crawl: HashSet<NodeAddress> = HashSet::new();
         for peer in bootstrap_peers {
-            to_crawl.insert(peer);
+            match NodeAddress::from_str(&peer) {
+                Ok(address) => {
+                    to_crawl.insert(address);
+                }
+                Err(err) => warn!("Ignoring bootstrap peer: {}", err),
+            }
         }
         Crawler {
             mobcoin_nodes: HashSet::new(),
@@ -93,28 +340,138 @@ impl Crawler {
             reachable_nodes: 0,
             crawl_duration: Duration::default(),
             crawl_time: String::default(),
+            config: CrawlConfig::default(),
         }
     }
+
+    /// Replace the default CrawlConfig.
+    pub fn with_config(mut self, config: CrawlConfig) -> Self {
+        self.config = config;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crawl::resolver::{StaticResolver, SystemResolver};
+    use crate::crawl::test_utils::{test_consensus_msg, test_quorum_set};
+    use mc_consensus_scp::{core_types::Ballot, msg::PreparePayload, test_utils::test_node_id};
 
     #[test]
-    fn bad_url_to_ip_port() {
-        let url = "foo:443";
-        let expected = (String::from("0.0.0.0"), 0);
-        let actual = CrawledNode::fragment_mc_url(String::from(url));
+    fn url_without_host_is_an_error() {
+        let actual = CrawledNode::fragment_mc_url(String::from("foo:443"));
+        assert!(matches!(actual, Err(Error::Uri(_))));
+        let actual = CrawledNode::fragment_mc_url(String::from("mc://node0.test.com"));
+        assert!(matches!(actual, Err(Error::Uri(_))));
+    }
+
+    #[test]
+    fn ip_url_to_ip_port() {
+        let url = "insecure-mc://127.0.0.1:3223";
+        let expected = (String::from("127.0.0.1"), 3223);
+        let actual = CrawledNode::fragment_mc_url(String::from(url)).unwrap();
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn unparsable_url_is_an_error() {
+        let actual = CrawledNode::fragment_mc_url(String::from("foo"));
+        assert!(matches!(actual, Err(Error::Uri(_))));
+    }
+
+    #[test]
+    fn unresolvable_hostname_is_an_error() {
+        let node = CrawledNode::new(
+            String::from("mc://does-not-exist.invalid:443"),
+            false,
+            McQuorumSet::empty(),
+        )
+        .unwrap();
+        assert!(matches!(
+            node.resolve_hostname(&SystemResolver),
+            Err(Error::Dns(_))
+        ));
+    }
+
+    #[test]
+    fn ip_addresses_resolve_to_themselves() {
+        let node =
+            CrawledNode::new(String::from("mc://[::1]:443"), false, McQuorumSet::empty()).unwrap();
+        assert_eq!(
+            node.resolve_hostname(&SystemResolver).unwrap(),
+            vec![IpAddr::from_str("::1").unwrap()]
+        );
+    }
+
+    #[test]
+    fn resolved_addresses_are_sorted() {
+        let node = CrawledNode::new(
+            String::from("mc://node0.test.com:443"),
+            false,
+            McQuorumSet::empty(),
+        )
+        .unwrap();
+        let ip = |ip| IpAddr::from_str(ip).unwrap();
+        let resolver = StaticResolver::default().with_host(
+            "node0.test.com",
+            &[
+                ip("2001:db8::1"),
+                ip("10.0.0.2"),
+                ip("10.0.0.1"),
+                ip("10.0.0.2"),
+            ],
+        );
+        assert_eq!(
+            node.resolve_hostname(&resolver).unwrap(),
+            vec![ip("10.0.0.1"), ip("10.0.0.2"), ip("2001:db8::1")]
+        );
+    }
+
+    #[test]
+    fn equivalent_uris_give_the_same_address() {
+        let expected = NodeAddress::from_str("mc://node0.test.com:443").unwrap();
+        for uri in [
+            "mc://NODE0.Test.com:443",
+            "mc://node0.test.com:443/",
+            "mc://node0.test.com",
+            " mc://node0.test.com.:443",
+        ] {
+            assert_eq!(NodeAddress::from_str(uri).unwrap(), expected);
+        }
+        assert_eq!(expected.to_string(), "mc://node0.test.com:443");
+        let insecure = NodeAddress::from_str("insecure-mc://node0.test.com:443").unwrap();
+        assert_ne!(insecure, expected);
+        assert_eq!(insecure.to_string(), "insecure-mc://node0.test.com:443");
+    }
+
+    #[test]
+    fn invalid_uri_is_not_an_address() {
+        for uri in ["localhost:443", "http://node0.test.com:443", ""] {
+            assert!(matches!(NodeAddress::from_str(uri), Err(Error::Uri(_))));
+        }
+    }
+
+    #[test]
+    fn address_from_responder_id() {
+        let responder_id = test_node_id(0).responder_id;
+        let actual = NodeAddress::from_responder_id(&responder_id, &CrawlConfig::default());
+        assert_eq!(
+            actual.unwrap().to_string(),
+            format!("mc://{}", responder_id)
+        );
+    }
+
     #[test]
     fn create_new_crawler() {
-        let bs_peers = vec![String::from("foo"), String::from("bar")];
-        let mut to_crawl: HashSet<String> = HashSet::new();
-        to_crawl.insert(String::from("foo"));
-        to_crawl.insert(String::from("bar"));
+        let bs_peers = vec![
+            String::from("mc://foo.com:443"),
+            String::from("mc://bar.com:443"),
+            String::from("baz"),
+        ];
+        let mut to_crawl: HashSet<NodeAddress> = HashSet::new();
+        to_crawl.insert(NodeAddress::from_str("mc://foo.com:443").unwrap());
+        to_crawl.insert(NodeAddress::from_str("mc://bar.com:443").unwrap());
         let expected = Crawler {
             mobcoin_nodes: HashSet::new(),
             to_crawl,
@@ -122,8 +479,54 @@ mod tests {
             reachable_nodes: 0,
             crawl_duration: Duration::default(),
             crawl_time: String::default(),
+            config: CrawlConfig::default(),
         };
         let actual = Crawler::new(bs_peers);
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn set_crawler_config() {
+        let config = CrawlConfig {
+            workers: 3,
+            max_retries: 0,
+            ..CrawlConfig::default()
+        };
+        let crawler = Crawler::new(vec![String::from("foo")]).with_config(config.clone());
+        assert_eq!(crawler.config, config);
+    }
+
+    #[test]
+    fn externalize_msg_to_scp_statement() {
+        let msg = test_consensus_msg(test_node_id(0), test_quorum_set(&[0, 1]));
+        let actual = ScpStatement::from_consensus_msg(&msg);
+        assert_eq!(actual.sender_id, test_node_id(0));
+        assert_eq!(actual.slot_index, 1);
+        assert_eq!(actual.phase, ScpPhase::Externalize);
+        assert_eq!(actual.ballot_counter, Some(1));
+        assert_eq!(actual.high_counter, Some(1));
+        assert_eq!(actual.prepared_counter, None);
+        assert_eq!(actual.signature, vec![0u8; 64]);
+    }
+
+    #[test]
+    fn prepare_msg_to_scp_statement() {
+        let mut msg = test_consensus_msg(test_node_id(0), test_quorum_set(&[0, 1]));
+        msg.scp_msg.slot_index = 42;
+        msg.scp_msg.topic = Topic::Prepare(PreparePayload {
+            B: Ballot::new(3, &[]),
+            P: Some(Ballot::new(2, &[])),
+            PP: None,
+            CN: 0,
+            HN: 2,
+        });
+        let actual = ScpStatement::from_consensus_msg(&msg);
+        assert_eq!(actual.slot_index, 42);
+        assert_eq!(actual.phase, ScpPhase::Prepare);
+        assert_eq!(actual.ballot_counter, Some(3));
+        assert_eq!(actual.prepared_counter, Some(2));
+        assert_eq!(actual.prepared_prime_counter, None);
+        assert_eq!(actual.commit_counter, Some(0));
+        assert_eq!(actual.high_counter, Some(2));
+    }
 }
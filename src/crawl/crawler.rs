@@ -1,142 +1,272 @@
 use crate::crawl::core_types::*;
+use crate::error::Error;
 
-use std::{collections::HashSet, str::FromStr, sync::Arc};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::str::FromStr;
 
-use grpcio::{ChannelBuilder, EnvBuilder};
-use mc_common::logger;
-use mc_consensus_api::{
-    consensus_peer::GetLatestMsgResponse, consensus_peer_grpc::ConsensusPeerApiClient,
-};
-use mc_consensus_scp::QuorumSet as McQuorumSet;
+use mc_consensus_api::consensus_peer::GetLatestMsgResponse;
 use mc_crypto_keys::Ed25519Public;
-use mc_peers::ConsensusMsg;
-use mc_util_grpc::ConnectionUriGrpcioChannel;
+use mc_peers::{ConsensusMsg, VerifiedConsensusMsg};
 use mc_util_serial::deserialize;
-use mc_util_uri::ConsensusClientUri as ClientUri;
 
 impl Crawler {
-    /// Opens an RPC channel to the peer which can be used for communication later
-    pub(crate) fn prepare_rpc(peer: String) -> Option<ConsensusPeerApiClient> {
-        let env = Arc::new(EnvBuilder::new().build());
-        let logger = logger::create_root_logger();
-        let node_uri = match ClientUri::from_str(&peer) {
-            Ok(uri) => Some(uri),
-            Err(_) => {
-                warn!("Error in Node URI: {}", peer);
-                return None;
-            }
-        };
-        let ch = ChannelBuilder::default_channel_builder(env)
-            .connect_to_uri(&node_uri.unwrap(), &logger);
-        let consensus_client = ConsensusPeerApiClient::new(ch);
-        Some(consensus_client)
+    /// The bytes of the RPC response are deserialised into the ConsensusMsg the node last sent
+    pub(crate) fn deserialise_payload(
+        response: GetLatestMsgResponse,
+    ) -> Result<ConsensusMsg, Error> {
+        if response.get_payload().is_empty() {
+            return Err(Error::Deserialise(String::from("empty payload")));
+        }
+        deserialize::<ConsensusMsg>(response.get_payload())
+            .map_err(|err| Error::Deserialise(err.to_string()))
     }
 
-    /// The bytes of the RPC response is deserialised into an McQuorumSet::QuorumSet
-    pub(crate) fn deserialise_payload_to_quorum_set(
-        response: GetLatestMsgResponse,
-    ) -> Option<McQuorumSet> {
-        let consensus_msg = if response.get_payload().is_empty() {
-            None
-        } else {
-            let consensus_msg = match deserialize::<ConsensusMsg>(response.get_payload()) {
-                Ok(cons_msg) => Some(cons_msg),
-                Err(_) => None,
-            };
-            consensus_msg
-        };
-        if let Some(scp_msg) = consensus_msg {
-            Some(scp_msg.scp_msg.quorum_set)
-        } else {
-            None
+    /// Checks the message's signature against the public key of the node that claims to have
+    /// sent it. This only shows that the message is self-consistent, whether the key is the one
+    /// the node's peers list it under is checked once the crawl is done, see
+    /// get_public_keys_from_quorum_sets.
+    pub(crate) fn verify_signature(msg: &ConsensusMsg) -> SignatureStatus {
+        match VerifiedConsensusMsg::try_from(msg.clone()) {
+            Ok(_) => SignatureStatus::Verified,
+            Err(err) => {
+                warn!(
+                    "Invalid signature on message from {}: {:?}",
+                    msg.scp_msg.sender_id.responder_id, err
+                );
+                SignatureStatus::Invalid
+            }
         }
     }
 
     /// 0. Add the reporting node to the set of crawled nodes
     /// 1. Add node to the set to discovered nodes
     /// 2. Iterate over all members of the Qset and add them to the set of peers that should be crawled
-    pub(crate) fn handle_discovered_node(&mut self, crawled_node: &String, node: &mut CrawledNode) {
+    pub(crate) fn handle_discovered_node(
+        &mut self,
+        crawled_node: &NodeAddress,
+        node: &mut CrawledNode,
+    ) {
         debug!("Handling crawled node {}..", crawled_node);
         self.to_crawl.remove(crawled_node);
         self.crawled.insert(crawled_node.to_owned());
         self.mobcoin_nodes.insert(node.to_owned());
         for member in node.quorum_set.nodes() {
-            let address = format!("{}{}", "mc://", member.responder_id);
+            let address = match NodeAddress::from_responder_id(&member.responder_id, &self.config) {
+                Ok(address) => address,
+                Err(err) => {
+                    warn!("Not crawling QSet member: {}", err);
+                    continue;
+                }
+            };
             if self.crawled.get(&address).is_some() {
                 continue;
             } else {
                 debug!("Adding {} to crawl queue.", address);
-                self.to_crawl.insert(address.clone());
+                self.to_crawl.insert(address);
             }
         }
     }
 
-    /// Looks for each node's PK in the other node's Qsets
+    /// Nodes that were reached under more than one address, e.g. via hostname and IP, share a PK.
+    /// For each PK only the node with the most information is kept, i.e. one we could reach and
+    /// that sent us a message.
+    pub(crate) fn merge_nodes_by_public_key(nodes: HashSet<CrawledNode>) -> HashSet<CrawledNode> {
+        let mut by_public_key: BTreeMap<Ed25519Public, CrawledNode> = BTreeMap::new();
+        let mut merged: HashSet<CrawledNode> = HashSet::new();
+        // Sorted so that the same node is kept no matter in which order the nodes were crawled
+        let mut nodes: Vec<CrawledNode> = nodes.into_iter().collect();
+        nodes.sort_by_key(|node| {
+            (
+                Reverse(node.online),
+                Reverse(node.latest_msg.is_some()),
+                node.domain.clone(),
+                node.port,
+            )
+        });
+        for node in nodes {
+            // Nodes whose PK we don't know can't be told apart
+            if node.public_key == Ed25519Public::default() {
+                merged.insert(node);
+                continue;
+            }
+            if let Some(kept) = by_public_key.get_mut(&node.public_key) {
+                debug!(
+                    "{}:{} is the same node as {}:{}.",
+                    node.domain, node.port, kept.domain, kept.port
+                );
+                for public_key in node.conflicting_keys {
+                    if !kept.conflicting_keys.contains(&public_key) {
+                        kept.conflicting_keys.push(public_key);
+                    }
+                }
+                kept.conflicting_keys.sort();
+            } else {
+                by_public_key.insert(node.public_key, node);
+            }
+        }
+        merged.extend(by_public_key.into_values());
+        merged
+    }
+
+    /// Nodes that sent us a message already carry the PK they signed it with. For all others we
+    /// look for their PK in the other nodes' Qsets, comparing normalised addresses. If peers list
+    /// different keys, the one most of them list is taken and the PK is left unset on a tie.
+    /// Keys that other nodes list for a node's responder ID but that differ from its PK are kept
+    /// as conflicting keys. Verified nodes whose PK none of their peers list are downgraded to
+    /// SignatureStatus::Mismatched.
     pub(crate) fn get_public_keys_from_quorum_sets(&self) -> HashSet<CrawledNode> {
+        let address_of = |responder_id: &str| {
+            NodeAddress::from_str(&format!("{}{}", self.config.peer_scheme(), responder_id)).ok()
+        };
+        // The addresses and keys each node's QSet lists, parsed once
+        let listings: Vec<(&CrawledNode, Vec<(NodeAddress, Ed25519Public)>)> = self
+            .mobcoin_nodes
+            .iter()
+            .map(|node| {
+                let members = node
+                    .quorum_set
+                    .nodes()
+                    .into_iter()
+                    .filter_map(|member| {
+                        address_of(&member.responder_id.to_string())
+                            .map(|address| (address, member.public_key))
+                    })
+                    .collect();
+                (node, members)
+            })
+            .collect();
         let mut mobcoin_nodes_with_pks: HashSet<CrawledNode> = HashSet::new();
-        // First get each node's PK
         for node in self.mobcoin_nodes.iter() {
             let mut node_now_with_pk = node.clone();
             // Add the node to set already otherwise it will be left out of the report if 1. the
             // crawler does not know other nodes 2. it wasn't found in the other qsets 3. sth else
             // I haven't thought of
-            let responder_id = format!("{}{}:{}", "mc://", node.domain, node.port);
-            for other_node in self.mobcoin_nodes.iter() {
-                if other_node != node {
-                    for member in other_node.quorum_set.nodes() {
-                        let address = format!("{}{}", "mc://", member.responder_id);
-                        if node.public_key == Ed25519Public::default() && responder_id == address {
-                            node_now_with_pk.public_key = member.public_key;
-                            break;
-                        }
+            let responder_id = format!("{}:{}", node.domain, node.port);
+            let address = address_of(&responder_id);
+            // How many peers list each key for the node
+            let mut listed_keys: BTreeMap<Ed25519Public, usize> = BTreeMap::new();
+            for (other_node, members) in listings.iter() {
+                if *other_node != node {
+                    let keys: BTreeSet<Ed25519Public> = members
+                        .iter()
+                        .filter(|(member, _)| Some(member) == address.as_ref())
+                        .map(|(_, public_key)| *public_key)
+                        .collect();
+                    for public_key in keys {
+                        *listed_keys.entry(public_key).or_insert(0) += 1;
                     }
                 }
             }
+            if node_now_with_pk.public_key == Ed25519Public::default() {
+                if let Some(public_key) = Self::majority_key(&listed_keys) {
+                    node_now_with_pk.public_key = public_key;
+                }
+            }
+            if node_now_with_pk.signature_status == SignatureStatus::Verified
+                && !listed_keys.is_empty()
+                && !listed_keys.contains_key(&node_now_with_pk.public_key)
+            {
+                warn!(
+                    "{} signs with a key none of its peers list for it.",
+                    responder_id
+                );
+                node_now_with_pk.signature_status = SignatureStatus::Mismatched;
+            }
+            node_now_with_pk.conflicting_keys = listed_keys
+                .into_keys()
+                .filter(|public_key| *public_key != node_now_with_pk.public_key)
+                .collect();
+            if !node_now_with_pk.conflicting_keys.is_empty() {
+                warn!(
+                    "Peers list {} conflicting keys for {}.",
+                    node_now_with_pk.conflicting_keys.len(),
+                    responder_id
+                );
+            }
             mobcoin_nodes_with_pks.insert(node_now_with_pk);
         }
         mobcoin_nodes_with_pks
     }
+
+    /// The key listed by the most peers, None if there is no key or several are tied.
+    fn majority_key(listed_keys: &BTreeMap<Ed25519Public, usize>) -> Option<Ed25519Public> {
+        let most = listed_keys.values().max()?;
+        let mut leaders = listed_keys.iter().filter(|(_, count)| *count == most);
+        match (leaders.next(), leaders.next()) {
+            (Some((public_key, _)), None) => Some(*public_key),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crawl::test_utils::{test_consensus_msg, test_payload, test_quorum_set, test_uri};
+    use mc_common::{NodeID, ResponderId};
     use mc_consensus_scp::test_utils::test_node_id;
-    use mc_consensus_scp::QuorumSetMember;
+    use mc_consensus_scp::{QuorumSet as McQuorumSet, QuorumSetMember};
 
     #[test]
-    fn invalid_peer_address_to_cons_peer() {
-        let peer = "localhost:443";
-        let actual = Crawler::prepare_rpc(String::from(peer));
-        assert!(actual.is_none());
+    fn empty_msg_to_consensus_msg() {
+        let msg = GetLatestMsgResponse::new();
+        let actual = Crawler::deserialise_payload(msg);
+        assert!(matches!(actual, Err(Error::Deserialise(_))));
     }
 
     #[test]
-    fn correct_peer_address_to_cons_peer() {
-        let peer = "mc://localhost:443";
-        let actual = Crawler::prepare_rpc(String::from(peer));
-        assert!(actual.is_some());
+    fn payload_to_consensus_msg() {
+        let mut msg = GetLatestMsgResponse::new();
+        msg.set_payload(test_payload(0, &[0, 1]));
+        let actual = Crawler::deserialise_payload(msg).unwrap();
+        assert_eq!(actual.scp_msg.sender_id, test_node_id(0));
+        assert_eq!(actual.scp_msg.quorum_set, test_quorum_set(&[0, 1]));
     }
 
     #[test]
-    fn empty_msg_to_quorum_set() {
-        let msg = GetLatestMsgResponse::new();
-        let actual = Crawler::deserialise_payload_to_quorum_set(msg);
-        assert!(actual.is_none());
+    fn unsigned_msg_is_invalid() {
+        let msg = test_consensus_msg(test_node_id(0), test_quorum_set(&[0, 1]));
+        let actual = Crawler::verify_signature(&msg);
+        assert_eq!(actual, SignatureStatus::Invalid);
     }
 
     #[test]
     fn record_new_node() {
         let mut crawler = Crawler::default();
         let reachable = false;
-        let crawled_node_uri = String::from("mc://test.node:11");
-        let mut crawled_node =
-            CrawledNode::new(crawled_node_uri.clone(), reachable, McQuorumSet::empty());
+        let crawled_node_uri = NodeAddress::from_str("mc://test.node:11").unwrap();
+        let mut crawled_node = CrawledNode::new(
+            crawled_node_uri.to_string(),
+            reachable,
+            McQuorumSet::empty(),
+        )
+        .unwrap();
         crawler.handle_discovered_node(&crawled_node_uri, &mut crawled_node);
         assert!(crawler.mobcoin_nodes.contains(&crawled_node));
         assert!(crawler.crawled.contains(&crawled_node_uri));
     }
 
+    #[test]
+    fn insecure_crawl_queues_insecure_peers() {
+        let mut crawler = Crawler::default().with_config(CrawlConfig {
+            insecure: true,
+            ..CrawlConfig::default()
+        });
+        let crawled_node_uri = NodeAddress::from_str("insecure-mc://test.node:11").unwrap();
+        let mut crawled_node = CrawledNode::new(
+            crawled_node_uri.to_string(),
+            true,
+            McQuorumSet::new(1, vec![QuorumSetMember::Node(test_node_id(1))]),
+        )
+        .unwrap();
+        crawler.handle_discovered_node(&crawled_node_uri, &mut crawled_node);
+        let expected = format!("insecure-mc://{}", test_node_id(1).responder_id);
+        assert!(crawler
+            .to_crawl
+            .contains(&NodeAddress::from_str(&expected).unwrap()));
+    }
+
     #[test]
     fn pks_from_qsets() {
         // TODO: Don't use default public key
@@ -158,6 +288,11 @@ mod tests {
                     ],
                 ),
                 online: false,
+                rpc_attempts: 1,
+                rpc_latency: None,
+                latest_msg: None,
+                signature_status: SignatureStatus::Unverified,
+                conflicting_keys: vec![],
             },
             CrawledNode {
                 public_key: node_0_pk,
@@ -171,6 +306,11 @@ mod tests {
                     ],
                 ),
                 online: false,
+                rpc_attempts: 1,
+                rpc_latency: None,
+                latest_msg: None,
+                signature_status: SignatureStatus::Unverified,
+                conflicting_keys: vec![],
             },
         ]);
         let actual = crawler.get_public_keys_from_quorum_sets();
@@ -180,6 +320,11 @@ mod tests {
                 domain: "mc://test.node0:11".to_string(),
                 port: 5678,
                 online: false,
+                rpc_attempts: 1,
+                rpc_latency: None,
+                latest_msg: None,
+                signature_status: SignatureStatus::Unverified,
+                conflicting_keys: vec![],
                 quorum_set: McQuorumSet::new(
                     2,
                     vec![
@@ -193,6 +338,11 @@ mod tests {
                 domain: "mc://test.node1:11".to_string(),
                 port: 8765,
                 online: false,
+                rpc_attempts: 1,
+                rpc_latency: None,
+                latest_msg: None,
+                signature_status: SignatureStatus::Unverified,
+                conflicting_keys: vec![],
                 quorum_set: McQuorumSet::new(
                     1,
                     vec![
@@ -207,4 +357,154 @@ mod tests {
             actual.contains(&node);
         }
     }
+
+    #[test]
+    fn own_key_is_kept_and_conflicting_listings_are_flagged() {
+        let listed_id = test_node_id(0);
+        let own_id = NodeID {
+            responder_id: listed_id.responder_id.clone(),
+            public_key: test_node_id(5).public_key,
+        };
+        let mut node_0 = CrawledNode::new(test_uri(0), true, test_quorum_set(&[0])).unwrap();
+        node_0.public_key = own_id.public_key;
+        node_0.latest_msg = Some(ScpStatement::from_consensus_msg(&test_consensus_msg(
+            own_id.clone(),
+            test_quorum_set(&[0]),
+        )));
+        let node_1 = CrawledNode::new(test_uri(1), false, test_quorum_set(&[0, 1])).unwrap();
+        let mut crawler = Crawler::default();
+        crawler.mobcoin_nodes = HashSet::from([node_0, node_1]);
+        let actual = crawler.get_public_keys_from_quorum_sets();
+        let node_0 = actual.iter().find(|node| node.online).unwrap();
+        assert_eq!(node_0.public_key, own_id.public_key);
+        assert_eq!(node_0.conflicting_keys, vec![listed_id.public_key]);
+        // Nobody else lists node 1
+        let node_1 = actual.iter().find(|node| !node.online).unwrap();
+        assert_eq!(node_1.public_key, Ed25519Public::default());
+        assert!(node_1.conflicting_keys.is_empty());
+    }
+
+    #[test]
+    fn verified_keys_must_be_listed_by_peers() {
+        let mut node_0 = CrawledNode::new(test_uri(0), true, test_quorum_set(&[0, 2])).unwrap();
+        node_0.public_key = test_node_id(5).public_key;
+        node_0.signature_status = SignatureStatus::Verified;
+        let mut node_1 = CrawledNode::new(test_uri(1), true, test_quorum_set(&[1])).unwrap();
+        node_1.public_key = test_node_id(1).public_key;
+        node_1.signature_status = SignatureStatus::Verified;
+        let node_2 = CrawledNode::new(test_uri(2), true, test_quorum_set(&[0, 1])).unwrap();
+        let mut crawler = Crawler::default();
+        crawler.mobcoin_nodes = HashSet::from([node_0.clone(), node_1.clone(), node_2.clone()]);
+        let actual = crawler.get_public_keys_from_quorum_sets();
+        let find = |node: &CrawledNode| {
+            actual
+                .iter()
+                .find(|crawled| crawled.domain == node.domain)
+                .unwrap()
+                .clone()
+        };
+        // Node 2 lists test_node_id(0)'s key for node 0, which signed with another one
+        assert_eq!(find(&node_0).signature_status, SignatureStatus::Mismatched);
+        assert_eq!(find(&node_0).quorum_set, node_0.quorum_set);
+        assert_eq!(find(&node_1).signature_status, SignatureStatus::Verified);
+    }
+
+    #[test]
+    fn key_falls_back_to_peers_listing() {
+        let node_0 = CrawledNode::new(test_uri(0), false, McQuorumSet::empty()).unwrap();
+        let node_1 = CrawledNode::new(test_uri(1), true, test_quorum_set(&[0, 1])).unwrap();
+        let mut crawler = Crawler::default();
+        crawler.mobcoin_nodes = HashSet::from([node_0, node_1]);
+        let actual = crawler.get_public_keys_from_quorum_sets();
+        let node_0 = actual.iter().find(|node| !node.online).unwrap();
+        assert_eq!(node_0.public_key, test_node_id(0).public_key);
+        assert!(node_0.conflicting_keys.is_empty());
+    }
+
+    #[test]
+    fn listings_are_compared_by_normalised_address() {
+        let node_0 = CrawledNode::new(test_uri(0), false, McQuorumSet::empty()).unwrap();
+        let listed_id = NodeID {
+            responder_id: ResponderId::from_str("NODE0.test.com.:8443").unwrap(),
+            public_key: test_node_id(0).public_key,
+        };
+        let node_1 = CrawledNode::new(
+            test_uri(1),
+            true,
+            McQuorumSet::new(1, vec![QuorumSetMember::Node(listed_id)]),
+        )
+        .unwrap();
+        let mut crawler = Crawler::default();
+        crawler.mobcoin_nodes = HashSet::from([node_0, node_1]);
+        let actual = crawler.get_public_keys_from_quorum_sets();
+        let node_0 = actual.iter().find(|node| !node.online).unwrap();
+        assert_eq!(node_0.public_key, test_node_id(0).public_key);
+    }
+
+    #[test]
+    fn key_listed_by_most_peers_is_taken() {
+        let listing = |node: u32, key: u32| {
+            let listed_id = NodeID {
+                responder_id: test_node_id(0).responder_id,
+                public_key: test_node_id(key).public_key,
+            };
+            CrawledNode::new(
+                test_uri(node),
+                true,
+                McQuorumSet::new(1, vec![QuorumSetMember::Node(listed_id)]),
+            )
+            .unwrap()
+        };
+        let node_0 = CrawledNode::new(test_uri(0), false, McQuorumSet::empty()).unwrap();
+        let crawl = |nodes: Vec<CrawledNode>| {
+            let mut crawler = Crawler::default();
+            crawler.mobcoin_nodes = nodes.into_iter().collect();
+            crawler.mobcoin_nodes.insert(node_0.clone());
+            crawler
+                .get_public_keys_from_quorum_sets()
+                .into_iter()
+                .find(|node| !node.online)
+                .unwrap()
+        };
+        let actual = crawl(vec![listing(1, 5), listing(2, 6), listing(3, 6)]);
+        assert_eq!(actual.public_key, test_node_id(6).public_key);
+        assert_eq!(actual.conflicting_keys, vec![test_node_id(5).public_key]);
+        // A tie leaves the key unknown
+        let actual = crawl(vec![listing(1, 5), listing(2, 6)]);
+        assert_eq!(actual.public_key, Ed25519Public::default());
+        assert_eq!(actual.conflicting_keys.len(), 2);
+    }
+
+    #[test]
+    fn nodes_sharing_a_key_are_merged() {
+        let public_key = test_node_id(0).public_key;
+        let mut by_hostname = CrawledNode::new(test_uri(0), true, test_quorum_set(&[0])).unwrap();
+        by_hostname.public_key = public_key;
+        by_hostname.conflicting_keys = vec![test_node_id(2).public_key];
+        let mut by_ip = CrawledNode::new(
+            String::from("mc://10.0.0.1:8443"),
+            false,
+            McQuorumSet::empty(),
+        )
+        .unwrap();
+        by_ip.public_key = public_key;
+        by_ip.conflicting_keys = vec![test_node_id(1).public_key];
+        let unknown_0 = CrawledNode::new(test_uri(3), false, McQuorumSet::empty()).unwrap();
+        let unknown_1 = CrawledNode::new(test_uri(4), false, McQuorumSet::empty()).unwrap();
+        let actual = Crawler::merge_nodes_by_public_key(HashSet::from([
+            by_hostname.clone(),
+            by_ip,
+            unknown_0,
+            unknown_1,
+        ]));
+        assert_eq!(actual.len(), 3);
+        let merged = actual
+            .iter()
+            .find(|node| node.public_key == public_key)
+            .unwrap();
+        assert_eq!(merged.domain, by_hostname.domain);
+        let mut expected_conflicts = vec![test_node_id(1).public_key, test_node_id(2).public_key];
+        expected_conflicts.sort();
+        assert_eq!(merged.conflicting_keys, expected_conflicts);
+    }
 }
@@ -1,7 +1,16 @@
+mod checkpoint;
 mod core_types;
 mod crawler;
 mod net;
+mod resolver;
+mod transport;
 
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+pub use checkpoint::*;
 pub use core_types::*;
 pub use crawler::*;
 pub use net::*;
+pub use resolver::*;
+pub use transport::*;
@@ -1,90 +1,262 @@
+use crate::crawl::checkpoint::CheckpointTimer;
 use crate::crawl::core_types::*;
+use crate::crawl::transport::{ConsensusTransport, GrpcTransport};
+use crate::error::Error;
 
 use chrono::{DateTime, Utc};
 use log::{info, warn};
-use std::time::Instant;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use mc_consensus_api::{
-    consensus_peer::GetLatestMsgResponse, consensus_peer_grpc::ConsensusPeerApiClient, empty,
-};
 use mc_consensus_scp::QuorumSet;
 
+/// Doubling the retry backoff stops here, unless the configured backoff is longer to begin with.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What crawling a single peer revealed about it.
+pub(crate) struct CrawlOutcome {
+    reachable: bool,
+    quorum_set: QuorumSet,
+    latest_msg: Option<ScpStatement>,
+    signature_status: SignatureStatus,
+    attempts: u32,
+    latency: Option<Duration>,
+}
+
 impl Crawler {
     /// This loop controls the entire crawl.
     /// The crawl ends when there are no more peers in the queue.
-    /// We call get_public_keys_from_quorum_sets in order to get fill the MobcoinFbas with PK instead of hostnames.
+    /// Each round crawls the current frontier concurrently, see crawl_frontier.
+    /// We call get_public_keys_from_quorum_sets in order to fill in the PKs of nodes that didn't send us a message.
+    /// Nodes that were crawled under several addresses are then merged by their PK.
     /// The MobcoinFbas contains all nodes that were found ready to be written as a JSON.
     pub fn crawl_network(&mut self) -> &mut Self {
-        let start = Instant::now();
-        let now: DateTime<Utc> = Utc::now();
-        info!("Starting crawl..");
-        loop {
-            for peer in self.to_crawl.clone().iter() {
-                self.crawl_node(peer);
-            }
-            if self.to_crawl.is_empty() {
-                break;
-            }
+        self.crawl_network_with(Arc::new(GrpcTransport::new()))
+    }
+
+    /// Same as crawl_network but peers are contacted through the given transport.
+    pub fn crawl_network_with(&mut self, transport: Arc<dyn ConsensusTransport>) -> &mut Self {
+        // A resumed crawl keeps its original timestamp and duration
+        let mut timer = CheckpointTimer::new(self.crawl_duration);
+        if self.crawl_time.is_empty() {
+            let now: DateTime<Utc> = Utc::now();
+            self.crawl_time = now.to_rfc3339();
         }
-        self.crawl_duration = start.elapsed();
-        self.crawl_time = now.to_rfc3339();
+        info!(
+            "Starting crawl with up to {} workers..",
+            self.config.workers.max(1)
+        );
+        while !self.to_crawl.is_empty() {
+            self.crawl_frontier(&transport, &mut timer);
+        }
+        self.crawl_duration = timer.crawl_duration();
         info!(
             "Crawl Summary - Crawled nodes: {}, Crawl Duration {:?}",
             self.crawled.len(),
             self.crawl_duration
         );
         let nodes_with_pks = self.get_public_keys_from_quorum_sets();
-        self.mobcoin_nodes = nodes_with_pks;
+        self.mobcoin_nodes = Self::merge_nodes_by_public_key(nodes_with_pks);
+        // A node reached under several addresses must only be counted once
+        self.reachable_nodes = self.mobcoin_nodes.iter().filter(|node| node.online).count();
+        self.checkpoint(&mut timer, true);
         self
     }
 
-    /// 1. Sends the given peer a gRPC.
+    /// Crawls all peers that are currently queued using up to `config.workers` threads.
+    /// Only the RPCs run on the worker threads; their outcomes are recorded here one at a time
+    /// so the bookkeeping is the same as for a sequential crawl.
+    /// Peers stay queued until their outcome is recorded so that checkpoints taken in between
+    /// include them. Peers discovered during this round are crawled in the next one.
+    fn crawl_frontier(
+        &mut self,
+        transport: &Arc<dyn ConsensusTransport>,
+        timer: &mut CheckpointTimer,
+    ) {
+        let frontier: Vec<NodeAddress> = self.to_crawl.iter().cloned().collect();
+        let workers = self.config.workers.max(1).min(frontier.len());
+        debug!("Crawling {} peers on {} workers..", frontier.len(), workers);
+        let queue = Arc::new(Mutex::new(frontier));
+        let (sender, receiver) = mpsc::channel();
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let sender = sender.clone();
+                let config = self.config.clone();
+                let transport = Arc::clone(transport);
+                thread::spawn(move || {
+                    while let Some(peer) = queue.lock().ok().and_then(|mut peers| peers.pop()) {
+                        let outcome = Self::crawl_node(&peer, &config, transport.as_ref());
+                        if sender.send((peer, outcome)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        // Only the workers hold senders now so the receiver is done once they have all finished
+        drop(sender);
+        for (peer, outcome) in receiver {
+            self.record_crawled_node(peer, outcome);
+            self.checkpoint(timer, false);
+        }
+        for handle in handles {
+            if handle.join().is_err() {
+                warn!("A crawl worker panicked.");
+            }
+        }
+        // Peers that were in flight while they were discovered again must not be crawled twice
+        let crawled = &self.crawled;
+        self.to_crawl.retain(|peer| !crawled.contains(peer));
+    }
+
+    /// 1. Sends the given peer a gRPC, retrying with exponential backoff as configured.
     /// 2. Get its QSet.
-    /// 3. Call the handle_discovered_node method on the peer.
-    fn crawl_node(&mut self, peer: &String) {
+    /// Returns None if the peer's URI is invalid and no RPC could be sent.
+    fn crawl_node(
+        peer: &NodeAddress,
+        config: &CrawlConfig,
+        transport: &dyn ConsensusTransport,
+    ) -> Option<CrawlOutcome> {
         info!("Crawling peer: {}", peer);
-        let mut reachable = false;
-        let quorum_set = if let Some(client) = Self::prepare_rpc(peer.clone()) {
-            if let Some(rpc_reply) = Self::send_rpc(client) {
-                self.reachable_nodes += 1;
-                reachable = true;
-                if let Some(qs) = Self::deserialise_payload_to_quorum_set(rpc_reply) {
-                    qs
-                } else {
-                    warn!("Couldn't deserialise message from {}.", peer);
-                    QuorumSet::empty()
+        let mut attempts = 0;
+        let mut backoff = config.retry_backoff;
+        let mut latency = None;
+        let rpc_reply = loop {
+            attempts += 1;
+            let sent = Instant::now();
+            match transport.get_latest_msg(&peer.to_string(), config) {
+                Ok(reply) => {
+                    latency = Some(sent.elapsed());
+                    break Some(reply);
                 }
-            } else {
-                warn!("Failure sending RPC to {} .", peer);
-                QuorumSet::empty()
+                Err(Error::Uri(_)) => return None,
+                Err(err) => debug!("Attempt {} on {} failed: {}", attempts, peer, err),
+            }
+            if attempts > config.max_retries {
+                break None;
+            }
+            debug!("Retrying {} in {:?}..", peer, backoff);
+            thread::sleep(backoff);
+            backoff = next_backoff(backoff, config);
+        };
+        let outcome = if let Some(rpc_reply) = rpc_reply {
+            let (quorum_set, latest_msg, signature_status) =
+                match Self::deserialise_payload(rpc_reply) {
+                    Ok(msg) => (
+                        msg.scp_msg.quorum_set.clone(),
+                        Some(ScpStatement::from_consensus_msg(&msg)),
+                        Self::verify_signature(&msg),
+                    ),
+                    Err(err) => {
+                        warn!("Couldn't deserialise message from {}: {}", peer, err);
+                        (QuorumSet::empty(), None, SignatureStatus::Unverified)
+                    }
+                };
+            CrawlOutcome {
+                reachable: true,
+                quorum_set,
+                latest_msg,
+                signature_status,
+                attempts,
+                latency,
             }
         } else {
-            // We didn't even send the RPC so no need to take note of the node
-            warn!("Terminating crawl on peer {} .", peer);
-            return;
+            warn!(
+                "Failure sending RPC to {} after {} attempts.",
+                peer, attempts
+            );
+            CrawlOutcome {
+                reachable: false,
+                quorum_set: QuorumSet::empty(),
+                latest_msg: None,
+                signature_status: SignatureStatus::Unverified,
+                attempts,
+                latency: None,
+            }
         };
-        let mut crawled = CrawledNode::new(peer.clone(), reachable, quorum_set);
-        self.handle_discovered_node(peer, &mut crawled);
+        Some(outcome)
     }
 
-    /// The RPC "get_latest_msg" expects an empty protobuf and returns the last ConsensusMsg a node
-    /// sent (see
-    /// https://github.com/mobilecoinfoundation/mobilecoin/blob/master/peers/src/consensus_msg.rs#L20 for the exact definition)
-    fn send_rpc(client: ConsensusPeerApiClient) -> Option<GetLatestMsgResponse> {
-        let response = match client.get_latest_msg(&empty::Empty::default()) {
-            Ok(reply) => Some(reply),
-            Err(_) => {
-                warn!("Error in RPC response.");
-                None
-            }
+    /// Call the handle_discovered_node method on the crawled peer.
+    fn record_crawled_node(&mut self, peer: NodeAddress, outcome: Option<CrawlOutcome>) {
+        let outcome = if let Some(outcome) = outcome {
+            outcome
+        } else {
+            // We didn't even send the RPC so no need to take note of the node, but we also
+            // shouldn't try again when another node lists it
+            warn!("Terminating crawl on peer {} .", peer);
+            self.crawled.insert(peer);
+            return;
         };
-        response
+        if outcome.reachable {
+            self.reachable_nodes += 1;
+        }
+        let mut crawled =
+            match CrawledNode::new(peer.to_string(), outcome.reachable, outcome.quorum_set) {
+                Ok(crawled) => crawled,
+                Err(err) => {
+                    warn!("Terminating crawl on peer {}: {}", peer, err);
+                    self.crawled.insert(peer);
+                    return;
+                }
+            };
+        crawled.rpc_attempts = outcome.attempts;
+        crawled.rpc_latency = outcome.latency;
+        // The node's own message tells us which key it signs with
+        if let Some(msg) = &outcome.latest_msg {
+            crawled.public_key = msg.sender_id.public_key;
+        }
+        crawled.latest_msg = outcome.latest_msg;
+        crawled.signature_status = outcome.signature_status;
+        self.handle_discovered_node(&peer, &mut crawled);
     }
 }
 
+/// Twice the last pause, capped at MAX_RETRY_BACKOFF instead of overflowing.
+fn next_backoff(backoff: Duration, config: &CrawlConfig) -> Duration {
+    backoff
+        .saturating_mul(2)
+        .min(MAX_RETRY_BACKOFF.max(config.retry_backoff))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crawl::test_utils::{test_payload, test_quorum_set, test_uri};
+    use crate::crawl::transport::MemoryTransport;
+    use std::str::FromStr;
+
+    fn test_config() -> CrawlConfig {
+        CrawlConfig {
+            max_retries: 1,
+            retry_backoff: Duration::from_millis(1),
+            ..CrawlConfig::default()
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let config = test_config();
+        assert_eq!(
+            next_backoff(Duration::from_millis(1), &config),
+            Duration::from_millis(2)
+        );
+        assert_eq!(
+            next_backoff(Duration::from_secs(40), &config),
+            MAX_RETRY_BACKOFF
+        );
+        assert_eq!(next_backoff(Duration::MAX, &config), MAX_RETRY_BACKOFF);
+        let config = CrawlConfig {
+            retry_backoff: Duration::from_secs(600),
+            ..CrawlConfig::default()
+        };
+        assert_eq!(
+            next_backoff(Duration::from_secs(600), &config),
+            Duration::from_secs(600)
+        );
+    }
 
     #[test]
     fn start_with_empty_queue_wont_panic() {
@@ -95,4 +267,119 @@ mod tests {
         assert_eq!(crawler.reachable_nodes, 0);
         assert_eq!(crawler.crawled.len(), 0);
     }
+
+    #[test]
+    fn invalid_uri_is_never_crawled() {
+        let transport = Arc::new(MemoryTransport::default());
+        let mut crawler = Crawler::new(vec![String::from("localhost:443")]);
+        crawler.crawl_network_with(transport.clone());
+        assert!(crawler.mobcoin_nodes.is_empty());
+        assert!(crawler.to_crawl.is_empty());
+        assert!(crawler.crawled.is_empty());
+        assert_eq!(transport.requests("localhost:443"), 0);
+    }
+
+    #[test]
+    fn equivalent_uris_are_crawled_once() {
+        let transport = Arc::new(
+            MemoryTransport::default()
+                .with_payload(&test_uri(0), test_payload(0, &[0, 1]))
+                .with_payload(&test_uri(1), test_payload(1, &[0, 1])),
+        );
+        let bootstrap_peers = vec![
+            test_uri(0).to_uppercase().replace("MC://", "mc://"),
+            format!("{}/", test_uri(0)),
+        ];
+        let mut crawler = Crawler::new(bootstrap_peers).with_config(test_config());
+        crawler.crawl_network_with(transport.clone());
+        assert_eq!(crawler.mobcoin_nodes.len(), 2);
+        assert_eq!(crawler.crawled.len(), 2);
+        assert_eq!(transport.requests(&test_uri(0)), 1);
+        assert_eq!(transport.requests(&test_uri(1)), 1);
+    }
+
+    #[test]
+    fn nodes_are_discovered_and_crawled_once() {
+        let transport = Arc::new(
+            MemoryTransport::default()
+                .with_payload(&test_uri(0), test_payload(0, &[0, 1, 2]))
+                .with_payload(&test_uri(1), test_payload(1, &[0, 1, 2]))
+                .with_payload(&test_uri(2), test_payload(2, &[1, 2])),
+        );
+        let mut crawler = Crawler::new(vec![test_uri(0)]).with_config(test_config());
+        crawler.crawl_network_with(transport.clone());
+        assert_eq!(crawler.mobcoin_nodes.len(), 3);
+        assert_eq!(crawler.reachable_nodes, 3);
+        assert!(crawler.to_crawl.is_empty());
+        for node in 0..3 {
+            let address = NodeAddress::from_str(&test_uri(node)).unwrap();
+            assert!(crawler.crawled.contains(&address));
+            assert_eq!(transport.requests(&test_uri(node)), 1);
+        }
+        for node in crawler.mobcoin_nodes.iter() {
+            let sender = &node.latest_msg.as_ref().unwrap().sender_id;
+            assert_eq!(node.public_key, sender.public_key);
+            assert!(node.rpc_latency.is_some());
+            assert!(node.conflicting_keys.is_empty());
+        }
+    }
+
+    #[test]
+    fn node_reached_under_two_addresses_is_counted_once() {
+        let transport = Arc::new(
+            MemoryTransport::default()
+                .with_payload(&test_uri(0), test_payload(0, &[0]))
+                .with_payload("mc://127.0.0.1:8443", test_payload(0, &[0])),
+        );
+        let bootstrap_peers = vec![test_uri(0), String::from("mc://127.0.0.1:8443")];
+        let mut crawler = Crawler::new(bootstrap_peers).with_config(test_config());
+        crawler.crawl_network_with(transport);
+        assert_eq!(crawler.crawled.len(), 2);
+        assert_eq!(crawler.mobcoin_nodes.len(), 1);
+        assert_eq!(crawler.reachable_nodes, 1);
+    }
+
+    #[test]
+    fn unreachable_node_is_retried_and_recorded() {
+        let transport =
+            MemoryTransport::default().with_payload(&test_uri(0), test_payload(0, &[0, 1]));
+        let mut crawler = Crawler::new(vec![test_uri(0)]).with_config(test_config());
+        crawler.crawl_network_with(Arc::new(transport));
+        assert_eq!(crawler.mobcoin_nodes.len(), 2);
+        assert_eq!(crawler.reachable_nodes, 1);
+        let unreachable = crawler
+            .mobcoin_nodes
+            .iter()
+            .find(|node| !node.online)
+            .unwrap();
+        assert_eq!(unreachable.domain, "node1.test.com");
+        assert_eq!(unreachable.rpc_attempts, 2);
+        assert!(unreachable.rpc_latency.is_none());
+        assert_eq!(unreachable.quorum_set, QuorumSet::empty());
+        assert!(unreachable.latest_msg.is_none());
+        assert_eq!(unreachable.signature_status, SignatureStatus::Unverified);
+    }
+
+    #[test]
+    fn strict_crawl_discovers_peers_of_unverified_nodes() {
+        // The test payloads aren't signed
+        let transport =
+            MemoryTransport::default().with_payload(&test_uri(0), test_payload(0, &[0, 1]));
+        let config = CrawlConfig {
+            strict: true,
+            ..test_config()
+        };
+        let mut crawler = Crawler::new(vec![test_uri(0)]).with_config(config);
+        crawler.crawl_network_with(Arc::new(transport));
+        // The unverified QSet is still used to discover peers, see MobcoinFbas::create_mobcoin_fbas
+        assert_eq!(crawler.mobcoin_nodes.len(), 2);
+        let node = crawler
+            .mobcoin_nodes
+            .iter()
+            .find(|node| node.online)
+            .unwrap();
+        assert_eq!(node.signature_status, SignatureStatus::Invalid);
+        assert_eq!(node.quorum_set, test_quorum_set(&[0, 1]));
+        assert!(node.latest_msg.is_some());
+    }
 }
@@ -0,0 +1,264 @@
+use crate::error::Error;
+use crate::io::parse_file;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Resolves the hostnames of crawled nodes to IP addresses, see CrawledNode::resolve_hostname.
+/// Reports are only enriched through this trait so that they can also be created offline and
+/// with fixed addresses.
+pub trait Resolver: Send + Sync {
+    /// All addresses of `host`, fails if there are none. IPv6 hosts are given in brackets, e.g.
+    /// "[::1]".
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>, Error>;
+}
+
+/// Asks the operating system's resolver.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+/// Remembers the addresses another Resolver found, so that a hostname shared by several nodes is
+/// only looked up once. Failures are not remembered.
+#[derive(Debug, Default)]
+pub struct CachingResolver<R: Resolver> {
+    inner: R,
+    cache: Mutex<HashMap<String, Vec<IpAddr>>>,
+}
+
+/// Resolves hostnames to fixed addresses, e.g. read from a hosts file.
+/// Hostnames without an entry are passed to the fallback resolver if there is one and can't be
+/// resolved otherwise, IP addresses always resolve to themselves.
+/// Clones share their hosts, so a hosts file only has to be read once even if every crawl uses a
+/// new fallback.
+#[derive(Clone, Default)]
+pub struct StaticResolver {
+    hosts: Arc<HashMap<String, Vec<IpAddr>>>,
+    fallback: Option<Arc<dyn Resolver>>,
+}
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>, Error> {
+        let address = format!("{}:{}", host, port);
+        let ips: Vec<IpAddr> = address
+            .to_socket_addrs()
+            .map_err(|err| Error::Dns(format!("{}: {}", address, err)))?
+            .map(|addr| addr.ip())
+            .collect();
+        if ips.is_empty() {
+            Err(Error::Dns(format!("{}: no addresses", address)))
+        } else {
+            Ok(ips)
+        }
+    }
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    /// A cache poisoned by a panicking lookup is still used, its entries are complete.
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>, Error> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(host)
+            .cloned();
+        if let Some(ips) = cached {
+            return Ok(ips);
+        }
+        let ips = self.inner.resolve(host, port)?;
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(host.to_string(), ips.clone());
+        Ok(ips)
+    }
+}
+
+impl StaticResolver {
+    /// Resolve `host` to `ips`, in addition to any addresses it already has.
+    pub fn with_host(mut self, host: &str, ips: &[IpAddr]) -> Self {
+        Arc::make_mut(&mut self.hosts)
+            .entry(normalise_host(host))
+            .or_default()
+            .extend_from_slice(ips);
+        self
+    }
+
+    /// Hand hostnames without an entry to `fallback`, replacing any previous fallback.
+    pub fn with_fallback(mut self, fallback: impl Resolver + 'static) -> Self {
+        self.fallback = Some(Arc::new(fallback));
+        self
+    }
+
+    /// Reads a file in the format of /etc/hosts: each line holds an IP address followed by the
+    /// hostnames resolving to it, "#" starts a comment. A hostname listed on several lines resolves
+    /// to all of their addresses.
+    pub fn from_hosts_str(hosts: &str) -> Result<Self, Error> {
+        let mut resolver = Self::default();
+        for (number, line) in hosts.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let ip = match fields.next() {
+                Some(ip) => ip.parse::<IpAddr>().map_err(|err| {
+                    Error::Deserialise(format!("line {}: {}: {}", number + 1, ip, err))
+                })?,
+                None => continue,
+            };
+            for host in fields {
+                resolver = resolver.with_host(host, &[ip]);
+            }
+        }
+        Ok(resolver)
+    }
+
+    /// Same as from_hosts_str but reads the hosts from the file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        parse_file(path, Self::from_hosts_str)
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>, Error> {
+        let host = normalise_host(host);
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        match (self.hosts.get(&host), self.fallback.as_ref()) {
+            (Some(ips), _) => Ok(ips.clone()),
+            (None, Some(fallback)) => fallback.resolve(&host, port),
+            (None, None) => Err(Error::Dns(format!("{}:{}: no static address", host, port))),
+        }
+    }
+}
+
+/// Hostnames are case insensitive, IPv6 addresses lose their brackets.
+fn normalise_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Counts the lookups it is asked for and resolves every host to 10.0.0.1.
+    #[derive(Default)]
+    struct CountingResolver(Mutex<u32>);
+
+    impl Resolver for CountingResolver {
+        fn resolve(&self, _host: &str, _port: u16) -> Result<Vec<IpAddr>, Error> {
+            *self.0.lock().unwrap() += 1;
+            Ok(vec![IpAddr::from_str("10.0.0.1").unwrap()])
+        }
+    }
+
+    #[test]
+    fn system_resolver_resolves_ip_addresses() {
+        let actual = SystemResolver.resolve("127.0.0.1", 443).unwrap();
+        assert_eq!(actual, vec![IpAddr::from_str("127.0.0.1").unwrap()]);
+        assert!(matches!(
+            SystemResolver.resolve("does-not-exist.invalid", 443),
+            Err(Error::Dns(_))
+        ));
+    }
+
+    #[test]
+    fn hosts_are_only_resolved_once() {
+        let resolver = CachingResolver::new(CountingResolver::default());
+        assert_eq!(
+            resolver.resolve("node0.test.com", 443),
+            resolver.resolve("node0.test.com", 8443)
+        );
+        resolver.resolve("node1.test.com", 443).unwrap();
+        assert_eq!(*resolver.inner.0.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn poisoned_cache_is_still_used() {
+        let resolver = CachingResolver::new(CountingResolver::default());
+        resolver.resolve("node0.test.com", 443).unwrap();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _cache = resolver.cache.lock().unwrap();
+            panic!("lookup panicked");
+        }));
+        assert!(resolver.cache.is_poisoned());
+        resolver.resolve("node0.test.com", 443).unwrap();
+        resolver.resolve("node1.test.com", 443).unwrap();
+        assert_eq!(*resolver.inner.0.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn hosts_file_can_be_parsed() {
+        let hosts = "# Multi-homed\n\
+                     10.0.0.1 node0.test.com node1.test.com\n\
+                     \n\
+                     2001:db8::1   NODE0.test.com # comment\n";
+        let resolver = StaticResolver::from_hosts_str(hosts).unwrap();
+        assert_eq!(
+            resolver.resolve("node0.test.com", 443).unwrap(),
+            vec![
+                IpAddr::from_str("10.0.0.1").unwrap(),
+                IpAddr::from_str("2001:db8::1").unwrap()
+            ]
+        );
+        assert_eq!(
+            resolver.resolve("node1.test.com.", 443).unwrap(),
+            vec![IpAddr::from_str("10.0.0.1").unwrap()]
+        );
+        assert_eq!(
+            resolver.resolve("[::1]", 443).unwrap(),
+            vec![IpAddr::from_str("::1").unwrap()]
+        );
+        assert!(matches!(
+            resolver.resolve("node2.test.com", 443),
+            Err(Error::Dns(_))
+        ));
+        assert!(matches!(
+            StaticResolver::from_hosts_str("not-an-ip node0.test.com"),
+            Err(Error::Deserialise(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_hosts_are_passed_to_the_fallback() {
+        let resolver = StaticResolver::default()
+            .with_host("node0.test.com", &[IpAddr::from_str("10.0.0.2").unwrap()])
+            .with_fallback(CountingResolver::default());
+        assert_eq!(
+            resolver.resolve("node0.test.com", 443).unwrap(),
+            vec![IpAddr::from_str("10.0.0.2").unwrap()]
+        );
+        assert_eq!(
+            resolver.resolve("node1.test.com", 443).unwrap(),
+            vec![IpAddr::from_str("10.0.0.1").unwrap()]
+        );
+    }
+
+    #[test]
+    fn clones_share_hosts_but_not_new_fallbacks() {
+        let hosts = StaticResolver::from_hosts_str("10.0.0.2 node0.test.com").unwrap();
+        let resolver = hosts.clone().with_fallback(CountingResolver::default());
+        assert!(Arc::ptr_eq(&hosts.hosts, &resolver.hosts));
+        assert_eq!(
+            resolver.resolve("node1.test.com", 443).unwrap(),
+            vec![IpAddr::from_str("10.0.0.1").unwrap()]
+        );
+        assert!(matches!(
+            hosts.resolve("node1.test.com", 443),
+            Err(Error::Dns(_))
+        ));
+    }
+}
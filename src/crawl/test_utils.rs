@@ -0,0 +1,54 @@
+//! Helpers for building the consensus messages the crawler receives in tests.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mc_common::NodeID;
+use mc_consensus_scp::{
+    core_types::Ballot,
+    msg::{ExternalizePayload, Msg, Topic},
+    test_utils::test_node_id,
+    QuorumSet, QuorumSetMember,
+};
+use mc_crypto_keys::Ed25519Signature;
+use mc_peers::ConsensusMsg;
+use mc_util_serial::serialize;
+
+/// The URI under which test_node_id(node) is crawled.
+pub(crate) fn test_uri(node: u32) -> String {
+    format!("mc://{}", test_node_id(node).responder_id)
+}
+
+/// The base64 encoded public key of test_node_id(node), as it appears in reports.
+pub(crate) fn test_key(node: u32) -> String {
+    STANDARD.encode(test_node_id(node).public_key)
+}
+
+/// A QSet that requires all of the given test nodes.
+pub(crate) fn test_quorum_set(members: &[u32]) -> QuorumSet {
+    QuorumSet::new(
+        members.len() as u32,
+        members
+            .iter()
+            .map(|member| QuorumSetMember::Node(test_node_id(*member)))
+            .collect(),
+    )
+}
+
+/// An unsigned ConsensusMsg from `sender` externalizing slot 1.
+/// Tests that need another phase can overwrite `scp_msg.topic`.
+pub(crate) fn test_consensus_msg(sender: NodeID, quorum_set: QuorumSet) -> ConsensusMsg {
+    let topic = Topic::Externalize(ExternalizePayload {
+        C: Ballot::new(1, &[]),
+        HN: 1,
+    });
+    ConsensusMsg {
+        scp_msg: Msg::new(sender, quorum_set, 1, topic),
+        prev_block_id: Default::default(),
+        signature: Ed25519Signature::try_from(&[0u8; 64][..]).unwrap(),
+    }
+}
+
+/// A serialised ConsensusMsg from test node `sender` whose QSet requires all of `members`.
+pub(crate) fn test_payload(sender: u32, members: &[u32]) -> Vec<u8> {
+    let msg = test_consensus_msg(test_node_id(sender), test_quorum_set(members));
+    serialize(&msg).unwrap()
+}
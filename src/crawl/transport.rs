@@ -0,0 +1,187 @@
+use crate::crawl::core_types::CrawlConfig;
+use crate::error::Error;
+
+use futures::executor::block_on;
+use grpcio::{CallOption, Channel, ChannelBuilder, EnvBuilder, Environment};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use mc_common::logger::{self, Logger};
+use mc_consensus_api::{
+    consensus_peer::GetLatestMsgResponse, consensus_peer_grpc::ConsensusPeerApiClient, empty,
+};
+use mc_util_grpc::ConnectionUriGrpcioChannel;
+use mc_util_uri::ConsensusClientUri as ClientUri;
+
+/// Fetches the latest consensus message a peer broadcast.
+/// The Crawler only talks to the network through this trait so that crawls can also be run against
+/// canned responses.
+pub trait ConsensusTransport: Send + Sync {
+    /// Makes a single get_latest_msg request to the peer at `uri`.
+    /// Retries are left to the caller.
+    fn get_latest_msg(
+        &self,
+        uri: &str,
+        config: &CrawlConfig,
+    ) -> Result<GetLatestMsgResponse, Error>;
+}
+
+/// Talks to peers over gRPC using the ConsensusPeerApiClient.
+/// The gRPC environment and its completion queue threads are shared by all channels.
+#[derive(Clone)]
+pub struct GrpcTransport {
+    env: Arc<Environment>,
+    logger: Logger,
+}
+
+/// Serves canned responses from memory instead of contacting the network.
+/// Peers without a response are treated as unreachable.
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    responses: HashMap<String, GetLatestMsgResponse>,
+    requests: Mutex<HashMap<String, u32>>,
+}
+
+impl GrpcTransport {
+    pub fn new() -> Self {
+        Self {
+            env: Arc::new(EnvBuilder::new().build()),
+            logger: logger::create_root_logger(),
+        }
+    }
+
+    /// Opens an RPC channel to the peer which can be used for communication later
+    pub(crate) fn prepare_rpc(&self, peer: &str) -> Result<Channel, Error> {
+        let node_uri = ClientUri::from_str(peer).map_err(|_| Error::Uri(peer.to_string()))?;
+        let ch = ChannelBuilder::default_channel_builder(Arc::clone(&self.env))
+            .connect_to_uri(&node_uri, &self.logger);
+        Ok(ch)
+    }
+
+    /// The RPC "get_latest_msg" expects an empty protobuf and returns the last ConsensusMsg a node
+    /// sent (see
+    /// https://github.com/mobilecoinfoundation/mobilecoin/blob/master/peers/src/consensus_msg.rs#L20 for the exact definition)
+    /// The channel has to be ready within the connect timeout and the call must finish before
+    /// the RPC deadline.
+    fn send_rpc(channel: &Channel, config: &CrawlConfig) -> Result<GetLatestMsgResponse, Error> {
+        if !block_on(channel.wait_for_connected(config.connect_timeout)) {
+            return Err(Error::Grpc(String::from("timed out connecting to peer")));
+        }
+        let client = ConsensusPeerApiClient::new(channel.clone());
+        let options = CallOption::default().timeout(config.rpc_deadline);
+        client
+            .get_latest_msg_opt(&empty::Empty::default(), options)
+            .map_err(|err| Error::Grpc(err.to_string()))
+    }
+}
+
+impl Default for GrpcTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConsensusTransport for GrpcTransport {
+    fn get_latest_msg(
+        &self,
+        uri: &str,
+        config: &CrawlConfig,
+    ) -> Result<GetLatestMsgResponse, Error> {
+        let channel = self.prepare_rpc(uri)?;
+        Self::send_rpc(&channel, config)
+    }
+}
+
+impl MemoryTransport {
+    /// Answer requests to `uri` with `response`.
+    pub fn with_response(mut self, uri: &str, response: GetLatestMsgResponse) -> Self {
+        self.responses.insert(uri.to_string(), response);
+        self
+    }
+
+    /// Answer requests to `uri` with a response carrying `payload`, usually a serialised
+    /// ConsensusMsg.
+    pub fn with_payload(self, uri: &str, payload: Vec<u8>) -> Self {
+        let mut response = GetLatestMsgResponse::new();
+        response.set_payload(payload);
+        self.with_response(uri, response)
+    }
+
+    /// How often `uri` was asked for its latest message.
+    pub fn requests(&self, uri: &str) -> u32 {
+        self.requests
+            .lock()
+            .map(|requests| requests.get(uri).copied().unwrap_or(0))
+            .unwrap_or(0)
+    }
+}
+
+impl ConsensusTransport for MemoryTransport {
+    fn get_latest_msg(
+        &self,
+        uri: &str,
+        _config: &CrawlConfig,
+    ) -> Result<GetLatestMsgResponse, Error> {
+        if ClientUri::from_str(uri).is_err() {
+            return Err(Error::Uri(uri.to_string()));
+        }
+        if let Ok(mut requests) = self.requests.lock() {
+            *requests.entry(uri.to_string()).or_insert(0) += 1;
+        }
+        self.responses
+            .get(uri)
+            .cloned()
+            .ok_or_else(|| Error::Grpc(format!("no response for {}", uri)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_peer_address_to_channel() {
+        let peer = "localhost:443";
+        let actual = GrpcTransport::new().prepare_rpc(peer);
+        assert_eq!(actual.err(), Some(Error::Uri(String::from(peer))));
+    }
+
+    #[test]
+    fn correct_peer_address_to_channel() {
+        let peer = "mc://localhost:443";
+        let actual = GrpcTransport::new().prepare_rpc(peer);
+        assert!(actual.is_ok());
+    }
+
+    #[test]
+    fn channels_share_the_environment() {
+        let transport = GrpcTransport::new();
+        let channels = [
+            transport.prepare_rpc("mc://node0.test.com:443").unwrap(),
+            transport.prepare_rpc("mc://node1.test.com:443").unwrap(),
+        ];
+        // Every channel keeps a reference to the environment it was built with
+        assert_eq!(Arc::strong_count(&transport.env), 1 + channels.len());
+    }
+
+    #[test]
+    fn memory_transport_serves_canned_payload() {
+        let uri = "mc://node0.test.com:8443";
+        let transport = MemoryTransport::default().with_payload(uri, vec![1, 2, 3]);
+        let actual = transport.get_latest_msg(uri, &CrawlConfig::default());
+        assert_eq!(actual.unwrap().get_payload(), &[1, 2, 3]);
+        assert_eq!(transport.requests(uri), 1);
+    }
+
+    #[test]
+    fn memory_transport_without_response_is_unreachable() {
+        let transport = MemoryTransport::default();
+        let actual = transport.get_latest_msg("mc://node0.test.com:8443", &CrawlConfig::default());
+        assert!(matches!(actual, Err(Error::Grpc(_))));
+        let actual = transport.get_latest_msg("node0.test.com:8443", &CrawlConfig::default());
+        assert!(matches!(actual, Err(Error::Uri(_))));
+    }
+}
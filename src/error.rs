@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// Everything that can go wrong in the library.
+/// Failures that only concern a single node are logged and recorded in the report instead, so a
+/// crawl is never aborted because of one misbehaving node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A node URI or responder ID could not be parsed
+    Uri(String),
+    /// A hostname could not be resolved to an IP address
+    Dns(String),
+    /// A node could not be reached or did not answer the RPC in time
+    Grpc(String),
+    /// A node's response could not be deserialised
+    Deserialise(String),
+    /// A GeoIP database could not be opened or had no entry for an IP address
+    GeoIp(String),
+    /// A file could not be read or written
+    Io(String),
+    /// A timestamp was not a valid RFC 3339 date and time
+    Timestamp(String),
+    /// The HTTP server could not be started
+    Http(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Uri(msg) => write!(f, "Invalid URI: {}", msg),
+            Error::Dns(msg) => write!(f, "DNS resolution failed: {}", msg),
+            Error::Grpc(msg) => write!(f, "gRPC failure: {}", msg),
+            Error::Deserialise(msg) => write!(f, "Deserialisation failed: {}", msg),
+            Error::GeoIp(msg) => write!(f, "GeoIP lookup failed: {}", msg),
+            Error::Io(msg) => write!(f, "I/O error: {}", msg),
+            Error::Timestamp(msg) => write!(f, "Invalid timestamp: {}", msg),
+            Error::Http(msg) => write!(f, "HTTP server error: {}", msg),
+        }
+    }
+}
+
+impl Error {
+    /// Prefixes the error's message with `context`, e.g. the file it concerns, keeping its kind.
+    pub(crate) fn with_context(self, context: impl fmt::Display) -> Self {
+        let prefix = |msg: String| format!("{}: {}", context, msg);
+        match self {
+            Error::Uri(msg) => Error::Uri(prefix(msg)),
+            Error::Dns(msg) => Error::Dns(prefix(msg)),
+            Error::Grpc(msg) => Error::Grpc(prefix(msg)),
+            Error::Deserialise(msg) => Error::Deserialise(prefix(msg)),
+            Error::GeoIp(msg) => Error::GeoIp(prefix(msg)),
+            Error::Io(msg) => Error::Io(prefix(msg)),
+            Error::Timestamp(msg) => Error::Timestamp(prefix(msg)),
+            Error::Http(msg) => Error::Http(prefix(msg)),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<maxminddb::MaxMindDBError> for Error {
+    fn from(err: maxminddb::MaxMindDBError) -> Self {
+        Error::GeoIp(err.to_string())
+    }
+}
@@ -0,0 +1,478 @@
+use crate::io::report::{CrawlReport, MobcoinNode, QuorumSet};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mc_crypto_keys::Ed25519Public;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Everything that changed between two crawls, see diff_reports.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportDiff {
+    /// The timestamp of the older crawl
+    pub from: String,
+    /// The timestamp of the newer crawl
+    pub to: String,
+    pub changes: Vec<NodeChange>,
+}
+
+/// A single change to a node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeChange {
+    /// The node's base64 encoded PK, empty if it isn't known
+    pub public_key: String,
+    /// The node's hostname:port
+    pub address: String,
+    #[serde(flatten)]
+    pub kind: ChangeKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "camelCase")]
+pub enum ChangeKind {
+    /// The node is only part of the newer crawl
+    Appeared,
+    /// The node is only part of the older crawl
+    Disappeared,
+    Active {
+        from: bool,
+        to: bool,
+    },
+    /// The node kept its key but is found at another hostname:port
+    Address {
+        from: String,
+        to: String,
+    },
+    /// The node kept its address but uses another key
+    PublicKey {
+        from: String,
+        to: String,
+    },
+    /// The threshold of the node's top level QSet changed
+    Threshold {
+        from: u64,
+        to: u64,
+    },
+    /// Validators were added to or removed from the node's QSet, including its inner QSets
+    Validators {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// The node's inner QSets changed while its validators stayed the same
+    InnerQuorumSets,
+    Isp {
+        from: String,
+        to: String,
+    },
+    Country {
+        from: String,
+        to: String,
+    },
+}
+
+/// Lists the changes from the `old` to the `new` crawl.
+/// Nodes are first matched by their public key, only the nodes left over, i.e. those without a
+/// known key or whose key changed, are then matched by their hostname and port.
+pub fn diff_reports(old: &CrawlReport, new: &CrawlReport) -> ReportDiff {
+    let mut old_nodes: Vec<&MobcoinNode> = old.nodes.nodes().iter().collect();
+    let mut new_nodes: Vec<&MobcoinNode> = new.nodes.nodes().iter().collect();
+    old_nodes.sort_by_key(|node| (address(node), node.public_key));
+    new_nodes.sort_by_key(|node| (address(node), node.public_key));
+    let mut matched = vec![false; old_nodes.len()];
+    // Keys are matched for all nodes first so that a node which moved doesn't lose its old
+    // address to another node that is now found there
+    let mut matches: Vec<Option<usize>> = new_nodes
+        .iter()
+        .map(|new_node| {
+            if new_node.public_key == Ed25519Public::default() {
+                return None;
+            }
+            let i = old_nodes.iter().enumerate().position(|(i, old_node)| {
+                !matched[i] && old_node.public_key == new_node.public_key
+            })?;
+            matched[i] = true;
+            Some(i)
+        })
+        .collect();
+    for (new_node, old_match) in new_nodes.iter().zip(matches.iter_mut()) {
+        if old_match.is_none() {
+            *old_match = old_nodes
+                .iter()
+                .enumerate()
+                .position(|(i, old_node)| !matched[i] && address(old_node) == address(new_node));
+            if let Some(i) = *old_match {
+                matched[i] = true;
+            }
+        }
+    }
+    let mut changes = vec![];
+    for (new_node, old_match) in new_nodes.iter().zip(matches) {
+        match old_match {
+            Some(i) => diff_nodes(old_nodes[i], new_node, &mut changes),
+            None => changes.push(change(new_node, ChangeKind::Appeared)),
+        }
+    }
+    for (old_node, _) in old_nodes
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+    {
+        changes.push(change(old_node, ChangeKind::Disappeared));
+    }
+    ReportDiff {
+        from: old.timestamp.clone(),
+        to: new.timestamp.clone(),
+        changes,
+    }
+}
+
+fn diff_nodes(old: &MobcoinNode, new: &MobcoinNode, changes: &mut Vec<NodeChange>) {
+    if address(old) != address(new) {
+        changes.push(change(
+            new,
+            ChangeKind::Address {
+                from: address(old),
+                to: address(new),
+            },
+        ));
+    }
+    if old.public_key != new.public_key {
+        changes.push(change(
+            new,
+            ChangeKind::PublicKey {
+                from: encode_key(&old.public_key),
+                to: encode_key(&new.public_key),
+            },
+        ));
+    }
+    if old.active != new.active {
+        changes.push(change(
+            new,
+            ChangeKind::Active {
+                from: old.active,
+                to: new.active,
+            },
+        ));
+    }
+    if old.quorum_set.threshold != new.quorum_set.threshold {
+        changes.push(change(
+            new,
+            ChangeKind::Threshold {
+                from: old.quorum_set.threshold,
+                to: new.quorum_set.threshold,
+            },
+        ));
+    }
+    let old_validators = all_validators(&old.quorum_set);
+    let new_validators = all_validators(&new.quorum_set);
+    if old_validators != new_validators {
+        changes.push(change(
+            new,
+            ChangeKind::Validators {
+                added: new_validators
+                    .difference(&old_validators)
+                    .cloned()
+                    .collect(),
+                removed: old_validators
+                    .difference(&new_validators)
+                    .cloned()
+                    .collect(),
+            },
+        ));
+    } else if old.quorum_set.inner_quorum_sets != new.quorum_set.inner_quorum_sets {
+        changes.push(change(new, ChangeKind::InnerQuorumSets));
+    }
+    if isp_changed(old, new) {
+        changes.push(change(
+            new,
+            ChangeKind::Isp {
+                from: old.isp.clone(),
+                to: new.isp.clone(),
+            },
+        ));
+    }
+    if old.geo_data.country_name != new.geo_data.country_name {
+        changes.push(change(
+            new,
+            ChangeKind::Country {
+                from: old.geo_data.country_name.clone(),
+                to: new.geo_data.country_name.clone(),
+            },
+        ));
+    }
+}
+
+/// ISP names drift between database releases, so they are only compared if the AS numbers
+/// aren't known.
+fn isp_changed(old: &MobcoinNode, new: &MobcoinNode) -> bool {
+    match (
+        old.geo_data.autonomous_system_number,
+        new.geo_data.autonomous_system_number,
+    ) {
+        (Some(old_asn), Some(new_asn)) => old_asn != new_asn,
+        _ => old.isp != new.isp,
+    }
+}
+
+fn change(node: &MobcoinNode, kind: ChangeKind) -> NodeChange {
+    NodeChange {
+        public_key: encode_key(&node.public_key),
+        address: address(node),
+        kind,
+    }
+}
+
+fn address(node: &MobcoinNode) -> String {
+    format!("{}:{}", node.hostname, node.port)
+}
+
+fn encode_key(public_key: &Ed25519Public) -> String {
+    if *public_key == Ed25519Public::default() {
+        String::default()
+    } else {
+        STANDARD.encode(public_key)
+    }
+}
+
+/// The validators of `quorum_set` and all of its inner QSets.
+fn all_validators(quorum_set: &QuorumSet) -> BTreeSet<String> {
+    let mut validators: BTreeSet<String> = quorum_set.validators.iter().cloned().collect();
+    for inner_quorum_set in quorum_set.inner_quorum_sets.iter() {
+        validators.extend(all_validators(inner_quorum_set));
+    }
+    validators
+}
+
+impl fmt::Display for ReportDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Changes from {} to {}:", self.from, self.to)?;
+        if self.changes.is_empty() {
+            return writeln!(f, "No changes.");
+        }
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for NodeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = if self.public_key.is_empty() {
+            self.address.clone()
+        } else {
+            format!("{} ({})", self.address, self.public_key)
+        };
+        match &self.kind {
+            ChangeKind::Appeared => write!(f, "+ {} appeared", node),
+            ChangeKind::Disappeared => write!(f, "- {} disappeared", node),
+            ChangeKind::Address { from, to } => write!(f, "~ {} moved: {} -> {}", node, from, to),
+            ChangeKind::Active { from, to } => write!(f, "~ {} active: {} -> {}", node, from, to),
+            ChangeKind::PublicKey { from, to } => {
+                write!(f, "~ {} public key: {} -> {}", node, from, to)
+            }
+            ChangeKind::Threshold { from, to } => {
+                write!(f, "~ {} quorum set threshold: {} -> {}", node, from, to)
+            }
+            ChangeKind::Validators { added, removed } => write!(
+                f,
+                "~ {} validators added: [{}], removed: [{}]",
+                node,
+                added.join(", "),
+                removed.join(", ")
+            ),
+            ChangeKind::InnerQuorumSets => write!(f, "~ {} inner quorum sets changed", node),
+            ChangeKind::Isp { from, to } => write!(f, "~ {} ISP: {:?} -> {:?}", node, from, to),
+            ChangeKind::Country { from, to } => {
+                write!(f, "~ {} country: {:?} -> {:?}", node, from, to)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::report::{GeoData, MobcoinFbas};
+    use mc_consensus_scp::test_utils::test_node_id;
+
+    fn node(index: u32, validators: &[u32]) -> MobcoinNode {
+        MobcoinNode {
+            public_key: test_node_id(index).public_key,
+            hostname: format!("node{}.test.com", index),
+            port: 443,
+            active: true,
+            quorum_set: QuorumSet {
+                threshold: validators.len() as u64,
+                validators: validators
+                    .iter()
+                    .map(|validator| STANDARD.encode(test_node_id(*validator).public_key))
+                    .collect(),
+                inner_quorum_sets: vec![],
+            },
+            isp: String::from("ISP A"),
+            geo_data: GeoData {
+                country_name: String::from("Germany"),
+                ..GeoData::default()
+            },
+            ..MobcoinNode::default()
+        }
+    }
+
+    fn report(timestamp: &str, nodes: Vec<MobcoinNode>) -> CrawlReport {
+        CrawlReport {
+            timestamp: timestamp.to_string(),
+            nodes: MobcoinFbas::new(nodes),
+            ..CrawlReport::default()
+        }
+    }
+
+    #[test]
+    fn identical_reports_have_no_changes() {
+        let old = report("t0", vec![node(0, &[0, 1]), node(1, &[0, 1])]);
+        let actual = diff_reports(&old, &old);
+        assert!(actual.changes.is_empty());
+        assert_eq!(actual.to_string(), "Changes from t0 to t0:\nNo changes.\n");
+    }
+
+    #[test]
+    fn node_changes_are_listed() {
+        let old = report("t0", vec![node(0, &[0, 1]), node(1, &[0, 1])]);
+        let mut changed = node(0, &[0, 2]);
+        changed.active = false;
+        changed.isp = String::from("ISP B");
+        changed.geo_data.country_name = String::from("France");
+        let new = report("t1", vec![changed, node(2, &[0, 2])]);
+        let actual = diff_reports(&old, &new);
+        let kinds: Vec<&ChangeKind> = actual.changes.iter().map(|change| &change.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &ChangeKind::Active {
+                    from: true,
+                    to: false
+                },
+                &ChangeKind::Validators {
+                    added: vec![STANDARD.encode(test_node_id(2).public_key)],
+                    removed: vec![STANDARD.encode(test_node_id(1).public_key)],
+                },
+                &ChangeKind::Isp {
+                    from: String::from("ISP A"),
+                    to: String::from("ISP B"),
+                },
+                &ChangeKind::Country {
+                    from: String::from("Germany"),
+                    to: String::from("France"),
+                },
+                &ChangeKind::Appeared,
+                &ChangeKind::Disappeared,
+            ]
+        );
+        assert_eq!(actual.changes[4].address, "node2.test.com:443");
+        assert_eq!(actual.changes[5].address, "node1.test.com:443");
+        let json = serde_json::to_value(&actual).unwrap();
+        assert_eq!(json["changes"][0]["change"], "active");
+        assert_eq!(json["changes"][0]["address"], "node0.test.com:443");
+        let text = actual.to_string();
+        assert!(text.contains("+ node2.test.com:443"));
+        assert!(text.contains("- node1.test.com:443"));
+    }
+
+    #[test]
+    fn nodes_are_matched_by_address_when_keys_differ() {
+        let old = report("t0", vec![node(0, &[0])]);
+        let mut rotated = node(0, &[0]);
+        rotated.public_key = test_node_id(5).public_key;
+        let mut unknown_key = node(1, &[0]);
+        unknown_key.public_key = Ed25519Public::default();
+        let new = report("t1", vec![rotated, unknown_key]);
+        let actual = diff_reports(&old, &new);
+        assert_eq!(
+            actual.changes[0].kind,
+            ChangeKind::PublicKey {
+                from: STANDARD.encode(test_node_id(0).public_key),
+                to: STANDARD.encode(test_node_id(5).public_key),
+            }
+        );
+        assert_eq!(actual.changes[1].kind, ChangeKind::Appeared);
+        assert!(actual.changes[1].public_key.is_empty());
+        assert_eq!(actual.changes.len(), 2);
+    }
+
+    #[test]
+    fn keys_are_matched_before_addresses() {
+        let old = report("t0", vec![node(0, &[0])]);
+        let mut unknown_key = node(0, &[0]);
+        unknown_key.public_key = Ed25519Public::default();
+        let mut moved = node(0, &[0]);
+        moved.hostname = String::from("node9.test.com");
+        // The node without a key sorts first but must not take the moved node's old address
+        let new = report("t1", vec![unknown_key, moved]);
+        let actual = diff_reports(&old, &new);
+        assert_eq!(actual.changes.len(), 2);
+        assert_eq!(actual.changes[0].kind, ChangeKind::Appeared);
+        assert_eq!(actual.changes[0].address, "node0.test.com:443");
+        assert!(actual.changes[0].public_key.is_empty());
+        assert_eq!(
+            actual.changes[1].kind,
+            ChangeKind::Address {
+                from: String::from("node0.test.com:443"),
+                to: String::from("node9.test.com:443"),
+            }
+        );
+        assert_eq!(
+            actual.changes[1].public_key,
+            STANDARD.encode(test_node_id(0).public_key)
+        );
+        assert!(actual.changes[1]
+            .to_string()
+            .contains("moved: node0.test.com:443 -> node9.test.com:443"));
+    }
+
+    #[test]
+    fn threshold_and_inner_quorum_set_changes() {
+        let mut old_node = node(0, &[0, 1]);
+        old_node.quorum_set.inner_quorum_sets = vec![QuorumSet {
+            threshold: 1,
+            validators: vec![STANDARD.encode(test_node_id(2).public_key)],
+            inner_quorum_sets: vec![],
+        }];
+        let mut new_node = old_node.clone();
+        new_node.quorum_set.threshold = 1;
+        new_node.quorum_set.inner_quorum_sets[0].threshold = 0;
+        let actual = diff_reports(&report("t0", vec![old_node]), &report("t1", vec![new_node]));
+        let kinds: Vec<&ChangeKind> = actual.changes.iter().map(|change| &change.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &ChangeKind::Threshold { from: 2, to: 1 },
+                &ChangeKind::InnerQuorumSets
+            ]
+        );
+    }
+
+    #[test]
+    fn isp_names_are_ignored_if_as_numbers_are_known() {
+        let mut old_node = node(0, &[0]);
+        old_node.geo_data.autonomous_system_number = Some(8075);
+        let mut renamed = old_node.clone();
+        renamed.isp = String::from("ISP A Corporation");
+        let actual = diff_reports(
+            &report("t0", vec![old_node.clone()]),
+            &report("t1", vec![renamed.clone()]),
+        );
+        assert!(actual.changes.is_empty());
+        let mut moved = renamed;
+        moved.geo_data.autonomous_system_number = Some(16509);
+        let actual = diff_reports(&report("t0", vec![old_node]), &report("t1", vec![moved]));
+        assert_eq!(
+            actual.changes[0].kind,
+            ChangeKind::Isp {
+                from: String::from("ISP A"),
+                to: String::from("ISP A Corporation"),
+            }
+        );
+    }
+}
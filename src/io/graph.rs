@@ -0,0 +1,347 @@
+use crate::io::report::{MobcoinFbas, MobcoinNode, QuorumSet};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mc_crypto_keys::Ed25519Public;
+use std::collections::BTreeMap;
+
+/// File names of trust graph exports start with this, followed by the crawl's timestamp and
+/// ".dot" or ".graphml".
+pub static GRAPH_FILE_PREFIX: &str = "mobilecoin_trust_graph_";
+
+/// Who trusts whom: an edge leads from every node to each validator in its QSet, including the
+/// validators of inner QSets. Exported as GraphViz DOT or GraphML.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct TrustGraph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<TrustEdge>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+struct GraphNode {
+    /// The base64 encoded PK or hostname:port if the key isn't known
+    id: String,
+    /// Empty for validators that only appear in QSets
+    hostname: String,
+    organisation: String,
+    active: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+struct TrustEdge {
+    from: String,
+    to: String,
+    /// The threshold of the (inner) QSet that lists `to`
+    threshold: u64,
+    /// Number of validators and inner QSets of that QSet
+    set_size: usize,
+    /// 0 for the top level QSet, 1 for its inner QSets and so on
+    depth: usize,
+}
+
+impl TrustGraph {
+    pub fn from_mobcoin_fbas(fbas: &MobcoinFbas) -> Self {
+        let mut nodes: BTreeMap<String, GraphNode> = BTreeMap::new();
+        let mut edges: Vec<TrustEdge> = vec![];
+        for node in fbas.nodes().iter() {
+            let id = node_id(node);
+            add_edges(&id, &node.quorum_set, 0, &mut edges);
+            nodes.insert(
+                id.clone(),
+                GraphNode {
+                    id,
+                    hostname: node.hostname.clone(),
+                    organisation: node.organization_id.clone().unwrap_or_default(),
+                    active: node.active,
+                },
+            );
+        }
+        for edge in edges.iter() {
+            nodes.entry(edge.to.clone()).or_insert_with(|| GraphNode {
+                id: edge.to.clone(),
+                ..GraphNode::default()
+            });
+        }
+        Self {
+            nodes: nodes.into_values().collect(),
+            edges,
+        }
+    }
+
+    /// Nodes are labelled with their hostname, organisation and active status, inactive nodes are
+    /// dashed. Edges are labelled "threshold/set size" of the QSet they stem from.
+    pub fn to_dot(&self) -> String {
+        let mut lines = vec![String::from("digraph mobilecoin {")];
+        for node in self.nodes.iter() {
+            let label = [
+                node.display_name(),
+                node.organisation.as_str(),
+                node.status(),
+            ]
+            .iter()
+            .filter(|part| !part.is_empty())
+            .map(|part| escape_dot(part))
+            .collect::<Vec<String>>()
+            .join("\\n");
+            lines.push(format!(
+                "  \"{}\" [label=\"{}\", hostname=\"{}\", organization=\"{}\", active={}{}];",
+                escape_dot(&node.id),
+                label,
+                escape_dot(&node.hostname),
+                escape_dot(&node.organisation),
+                node.active,
+                if node.active { "" } else { ", style=dashed" },
+            ));
+        }
+        for edge in self.edges.iter() {
+            lines.push(format!(
+                "  \"{}\" -> \"{}\" [label=\"{}/{}\", threshold={}, set_size={}, depth={}];",
+                escape_dot(&edge.from),
+                escape_dot(&edge.to),
+                edge.threshold,
+                edge.set_size,
+                edge.threshold,
+                edge.set_size,
+                edge.depth,
+            ));
+        }
+        lines.push(String::from("}"));
+        lines.join("\n") + "\n"
+    }
+
+    /// The same graph with the node and edge attributes as GraphML data, e.g. for Gephi or yEd.
+    pub fn to_graphml(&self) -> String {
+        let mut lines = vec![
+            String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#),
+            String::from(r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#),
+        ];
+        for (id, domain, key_type) in [
+            ("label", "node", "string"),
+            ("hostname", "node", "string"),
+            ("organization", "node", "string"),
+            ("active", "node", "boolean"),
+            ("threshold", "edge", "long"),
+            ("setSize", "edge", "int"),
+            ("depth", "edge", "int"),
+        ] {
+            lines.push(format!(
+                r#"  <key id="{0}" for="{1}" attr.name="{0}" attr.type="{2}"/>"#,
+                id, domain, key_type
+            ));
+        }
+        lines.push(String::from(
+            r#"  <graph id="mobilecoin" edgedefault="directed">"#,
+        ));
+        for node in self.nodes.iter() {
+            lines.push(format!(r#"    <node id="{}">"#, escape_xml(&node.id)));
+            lines.push(data("label", node.display_name()));
+            lines.push(data("hostname", &node.hostname));
+            lines.push(data("organization", &node.organisation));
+            lines.push(data("active", &node.active.to_string()));
+            lines.push(String::from("    </node>"));
+        }
+        for edge in self.edges.iter() {
+            lines.push(format!(
+                r#"    <edge source="{}" target="{}">"#,
+                escape_xml(&edge.from),
+                escape_xml(&edge.to)
+            ));
+            lines.push(data("threshold", &edge.threshold.to_string()));
+            lines.push(data("setSize", &edge.set_size.to_string()));
+            lines.push(data("depth", &edge.depth.to_string()));
+            lines.push(String::from("    </edge>"));
+        }
+        lines.push(String::from("  </graph>"));
+        lines.push(String::from("</graphml>"));
+        lines.join("\n") + "\n"
+    }
+}
+
+impl GraphNode {
+    /// Validators that only appear in QSets are known by their key alone.
+    fn display_name(&self) -> &str {
+        if self.hostname.is_empty() {
+            &self.id
+        } else {
+            &self.hostname
+        }
+    }
+
+    fn status(&self) -> &str {
+        if self.active {
+            "active"
+        } else if self.hostname.is_empty() {
+            "unknown"
+        } else {
+            "inactive"
+        }
+    }
+}
+
+fn node_id(node: &MobcoinNode) -> String {
+    if node.public_key == Ed25519Public::default() {
+        format!("{}:{}", node.hostname, node.port)
+    } else {
+        STANDARD.encode(node.public_key)
+    }
+}
+
+fn add_edges(from: &str, quorum_set: &QuorumSet, depth: usize, edges: &mut Vec<TrustEdge>) {
+    let set_size = quorum_set.validators.len() + quorum_set.inner_quorum_sets.len();
+    for validator in quorum_set.validators.iter() {
+        edges.push(TrustEdge {
+            from: from.to_string(),
+            to: validator.clone(),
+            threshold: quorum_set.threshold,
+            set_size,
+            depth,
+        });
+    }
+    for inner_quorum_set in quorum_set.inner_quorum_sets.iter() {
+        add_edges(from, inner_quorum_set, depth + 1, edges);
+    }
+}
+
+fn data(key: &str, value: &str) -> String {
+    format!(r#"      <data key="{}">{}</data>"#, key, escape_xml(value))
+}
+
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::test_utils::test_key;
+    use mc_consensus_scp::test_utils::test_node_id;
+
+    /// Node 0 requires 2 of {1, {1 of 2, 3}}, node 1 requires node 0 and is inactive.
+    fn test_graph() -> TrustGraph {
+        let node = |index: u32, active: bool, quorum_set: QuorumSet| MobcoinNode {
+            public_key: test_node_id(index).public_key,
+            hostname: format!("node{}.test.com", index),
+            port: 443,
+            active,
+            organization_id: Some(String::from("test.com")),
+            quorum_set,
+            ..MobcoinNode::default()
+        };
+        TrustGraph::from_mobcoin_fbas(&MobcoinFbas::new(vec![
+            node(
+                0,
+                true,
+                QuorumSet {
+                    threshold: 2,
+                    validators: vec![test_key(1)],
+                    inner_quorum_sets: vec![QuorumSet {
+                        threshold: 1,
+                        validators: vec![test_key(2), test_key(3)],
+                        inner_quorum_sets: vec![],
+                    }],
+                },
+            ),
+            node(
+                1,
+                false,
+                QuorumSet {
+                    threshold: 1,
+                    validators: vec![test_key(0)],
+                    inner_quorum_sets: vec![],
+                },
+            ),
+        ]))
+    }
+
+    #[test]
+    fn edges_include_inner_quorum_sets() {
+        let graph = test_graph();
+        // Nodes 2 and 3 only appear in node 0's QSet
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(
+            graph.edges,
+            vec![
+                TrustEdge {
+                    from: test_key(0),
+                    to: test_key(1),
+                    threshold: 2,
+                    set_size: 2,
+                    depth: 0,
+                },
+                TrustEdge {
+                    from: test_key(0),
+                    to: test_key(2),
+                    threshold: 1,
+                    set_size: 2,
+                    depth: 1,
+                },
+                TrustEdge {
+                    from: test_key(0),
+                    to: test_key(3),
+                    threshold: 1,
+                    set_size: 2,
+                    depth: 1,
+                },
+                TrustEdge {
+                    from: test_key(1),
+                    to: test_key(0),
+                    threshold: 1,
+                    set_size: 1,
+                    depth: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dot_labels_nodes_and_edges() {
+        let dot = test_graph().to_dot();
+        assert!(dot.starts_with("digraph mobilecoin {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(&format!(
+            "\"{}\" [label=\"node0.test.com\\ntest.com\\nactive\", hostname=\"node0.test.com\", \
+             organization=\"test.com\", active=true];",
+            test_key(0)
+        )));
+        assert!(dot.contains("\\ninactive\", hostname=\"node1.test.com\""));
+        assert!(dot.contains("active=false, style=dashed];"));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [label=\"1/2\", threshold=1, set_size=2, depth=1];",
+            test_key(0),
+            test_key(2)
+        )));
+        assert!(dot.contains(&format!("[label=\"{}\\nunknown\"", test_key(3))));
+    }
+
+    #[test]
+    fn graphml_contains_nodes_and_edges() {
+        let graphml = test_graph().to_graphml();
+        assert!(graphml.contains(r#"<graph id="mobilecoin" edgedefault="directed">"#));
+        assert_eq!(graphml.matches("<node id=").count(), 4);
+        assert_eq!(graphml.matches("<edge source=").count(), 4);
+        assert!(graphml.contains(&format!(
+            r#"<edge source="{}" target="{}">"#,
+            test_key(1),
+            test_key(0)
+        )));
+        assert!(graphml.contains(r#"<data key="organization">test.com</data>"#));
+        assert!(graphml.contains(r#"<data key="active">false</data>"#));
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(escape_dot(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(
+            escape_xml(r#"<a & 'b' "c">"#),
+            "&lt;a &amp; &apos;b&apos; &quot;c&quot;&gt;"
+        );
+    }
+}
@@ -1,3 +1,25 @@
+pub mod diff;
+pub mod graph;
+pub mod organisations;
 pub mod report;
 
+pub use diff::*;
+pub use graph::*;
+pub use organisations::*;
 pub use report::*;
+
+use crate::error::Error;
+
+use std::fs;
+use std::path::Path;
+
+/// Reads the file at `path` and parses its content with `parse`.
+/// All errors are prefixed with the path, parse errors keep their kind.
+pub(crate) fn parse_file<T>(
+    path: &Path,
+    parse: impl FnOnce(&str) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| Error::Io(format!("{}: {}", path.display(), err)))?;
+    parse(&content).map_err(|err| err.with_context(path.display()))
+}
@@ -0,0 +1,250 @@
+use crate::error::Error;
+use crate::io::parse_file;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// File names of generated organisations JSONs start with this, followed by the crawl's
+/// timestamp.
+pub static ORGANISATIONS_FILE_PREFIX: &str = "mobilecoin_organisations_";
+
+/// An organisation running one or more validators.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Organisation {
+    /// Referenced by the organizationId of the organisation's nodes, empty in hand-written files
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub name: String,
+    /// The base64 encoded public keys of the organisation's validators
+    pub validators: Vec<String>,
+}
+
+/// The organisations JSON in stellarbeat.io format as used by the fbas_analyzer, e.g.
+/// crawl_data/mobilecoin_organisations_2021-08-02_created_manually.json.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Organisations(Vec<Organisation>);
+
+impl Organisations {
+    pub fn new(organisations: Vec<Organisation>) -> Self {
+        Self(organisations)
+    }
+
+    pub fn organisations(&self) -> &[Organisation] {
+        &self.0
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|err| Error::Deserialise(err.to_string()))
+    }
+
+    /// Same as from_json_str but reads the JSON from the file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        parse_file(path, Self::from_json_str)
+    }
+}
+
+/// Decides which organisation runs a node, see MobcoinFbas::assign_organisations.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct OrganisationMapping {
+    /// Organisation names by hostname or domain
+    names: HashMap<String, String>,
+    by_domain: bool,
+}
+
+impl OrganisationMapping {
+    /// Groups nodes by the registrable domain of their hostnames, e.g. peer1.prod.mobilecoinww.com
+    /// and peer2.prod.mobilecoinww.com are both run by "mobilecoinww.com".
+    pub fn by_domain() -> Self {
+        Self {
+            names: HashMap::new(),
+            by_domain: true,
+        }
+    }
+
+    /// Reads a JSON object mapping hostnames or domains to organisation names.
+    /// A domain applies to all of its subdomains and the longest matching entry wins. Nodes
+    /// without an entry are grouped by their registrable domain if `by_domain` is set.
+    pub fn from_json_str(json: &str, by_domain: bool) -> Result<Self, Error> {
+        let names: HashMap<String, String> =
+            serde_json::from_str(json).map_err(|err| Error::Deserialise(err.to_string()))?;
+        Ok(Self {
+            names: names
+                .into_iter()
+                .map(|(domain, name)| (normalise_hostname(&domain), name))
+                .collect(),
+            by_domain,
+        })
+    }
+
+    /// Same as from_json_str but reads the JSON from the file at `path`.
+    pub fn from_file(path: &Path, by_domain: bool) -> Result<Self, Error> {
+        parse_file(path, |json| Self::from_json_str(json, by_domain))
+    }
+
+    /// The name of the organisation running the node at `hostname`, if it is known.
+    pub fn organisation_of(&self, hostname: &str) -> Option<String> {
+        let hostname = normalise_hostname(hostname);
+        self.names
+            .iter()
+            .filter(|(domain, _)| {
+                hostname == **domain || hostname.ends_with(&format!(".{}", domain))
+            })
+            .max_by_key(|(domain, _)| domain.len())
+            .map(|(_, name)| name.clone())
+            .or_else(|| {
+                if self.by_domain {
+                    registrable_domain(&hostname)
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Derives the organizationId from the organisation's name.
+pub fn organisation_id(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Derives an organizationId for each of the `names`, see organisation_id, and returns them by
+/// name. A name whose id was already taken by an earlier one gets the first free numeric suffix,
+/// e.g. "a-b" and "a-b-2" for "A B" and "a-b".
+pub fn organisation_ids<'a>(names: impl IntoIterator<Item = &'a str>) -> HashMap<String, String> {
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let mut taken: HashSet<String> = HashSet::new();
+    for name in names {
+        if ids.contains_key(name) {
+            continue;
+        }
+        let base = organisation_id(name);
+        let mut id = base.clone();
+        let mut suffix = 2;
+        while taken.contains(&id) {
+            id = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        if id != base {
+            warn!(
+                "{} has the same organizationId as another organisation, using {}.",
+                name, id
+            );
+        }
+        taken.insert(id.clone());
+        ids.insert(name.to_string(), id);
+    }
+    ids
+}
+
+fn normalise_hostname(hostname: &str) -> String {
+    hostname.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// IP addresses don't have a registrable domain.
+fn registrable_domain(hostname: &str) -> Option<String> {
+    if hostname.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    psl::domain_str(hostname).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn historical_organisations_can_be_loaded() {
+        let organisations = Organisations::from_file(Path::new(
+            "crawl_data/mobilecoin_organisations_2021-08-02_created_manually.json",
+        ))
+        .unwrap();
+        assert_eq!(organisations.organisations().len(), 7);
+        assert_eq!(organisations.organisations()[0].name, "Dreamhost");
+        assert_eq!(
+            organisations.organisations()[0].validators,
+            vec![String::from("9uEO9eq8TKU0vrKt1R6p4wzkGJX7HbXDXyzs8HEX21g=")]
+        );
+    }
+
+    #[test]
+    fn organisations_by_domain() {
+        let mapping = OrganisationMapping::by_domain();
+        assert_eq!(
+            mapping.organisation_of("peer1.prod.mobilecoinww.com"),
+            Some(String::from("mobilecoinww.com"))
+        );
+        assert_eq!(
+            mapping.organisation_of("Peer2.prod.MobileCoinWW.com."),
+            Some(String::from("mobilecoinww.com"))
+        );
+        assert_eq!(
+            mapping.organisation_of("node.example.co.uk"),
+            Some(String::from("example.co.uk"))
+        );
+        assert_eq!(mapping.organisation_of("127.0.0.1"), None);
+        assert_eq!(mapping.organisation_of("::1"), None);
+    }
+
+    #[test]
+    fn organisations_by_mapping() {
+        let json = r#"{
+            "bdnodes.net": "Blockdaemon",
+            "binance.mobilecoin.bdnodes.net": "Binance",
+            "127.0.0.1": "Localhost"
+        }"#;
+        let mapping = OrganisationMapping::from_json_str(json, true).unwrap();
+        // The longest match wins
+        assert_eq!(
+            mapping.organisation_of("binance.mobilecoin.bdnodes.net"),
+            Some(String::from("Binance"))
+        );
+        assert_eq!(
+            mapping.organisation_of("blockdaemon.mobilecoin.bdnodes.net"),
+            Some(String::from("Blockdaemon"))
+        );
+        assert_eq!(
+            mapping.organisation_of("127.0.0.1"),
+            Some(String::from("Localhost"))
+        );
+        // Only whole labels match
+        assert_eq!(
+            mapping.organisation_of("notbdnodes.net"),
+            Some(String::from("notbdnodes.net"))
+        );
+        let mapping_only = OrganisationMapping::from_json_str(json, false).unwrap();
+        assert_eq!(mapping_only.organisation_of("notbdnodes.net"), None);
+        assert!(matches!(
+            OrganisationMapping::from_json_str("[]", true),
+            Err(Error::Deserialise(_))
+        ));
+    }
+
+    #[test]
+    fn ids_are_derived_from_names() {
+        assert_eq!(
+            organisation_id("MobileCoin Worldwide"),
+            "mobilecoin-worldwide"
+        );
+        assert_eq!(organisation_id("mobilecoinww.com"), "mobilecoinww.com");
+    }
+
+    #[test]
+    fn colliding_ids_are_disambiguated() {
+        let actual = organisation_ids(["A B", "a-b", "a-b-2", "A B"]);
+        assert_eq!(actual.len(), 3);
+        assert_eq!(actual["A B"], "a-b");
+        assert_eq!(actual["a-b"], "a-b-2");
+        assert_eq!(actual["a-b-2"], "a-b-2-2");
+    }
+}
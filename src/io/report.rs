@@ -1,37 +1,189 @@
-use crate::crawl::{CrawledNode, Crawler};
-use crate::stats::{Database, DbReader};
+use crate::analysis::AnalysisSummary;
+use crate::crawl::{
+    CachingResolver, CrawledNode, Crawler, Resolver, ScpPhase, ScpStatement, SignatureStatus,
+    SystemResolver,
+};
+use crate::error::Error;
+use crate::io::organisations::{
+    organisation_ids, Organisation, OrganisationMapping, Organisations,
+};
+use crate::io::parse_file;
+use crate::stats::{DatabaseInfo, GeoLookup};
 
 use base64::{engine::general_purpose::STANDARD, Engine};
 use mc_consensus_scp::{QuorumSet as McQuorumSet, QuorumSetMember};
 use mc_crypto_keys::Ed25519Public;
-use serde::{Serialize, Serializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
 use std::time::Duration;
 
-#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize)]
+/// File names of MobcoinFbas JSONs start with this, followed by the crawl's timestamp.
+pub static NODES_FILE_PREFIX: &str = "mobilecoin_nodes_";
+
+/// File names of CrawlReport JSONs start with this, followed by the crawl's timestamp.
+pub static REPORT_FILE_PREFIX: &str = "mobilecoin_crawl_report_";
+
+/// Nodes whose latest message is more than this many slots behind the highest slot seen during
+/// the crawl aren't validating.
+pub static VALIDATING_SLOT_LAG: u64 = 5;
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 /// Representation of a crawl::CrawledNode node in stellarbeat.io format.
 /// The MobcoinFbas is a collection of MobcoinNodes.
 pub struct MobcoinNode {
-    #[serde(serialize_with = "key_to_base64")]
+    #[serde(serialize_with = "key_to_base64", deserialize_with = "key_from_base64")]
     pub public_key: Ed25519Public,
+    /// The name the operator gave the node, empty as the crawler doesn't learn it
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
     pub hostname: String,
+    /// The first of the addresses, empty if the hostname couldn't be resolved
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ip: String,
     pub port: u16,
     pub active: bool,
+    /// Always set as the crawler only finds validators, also for reports that leave it out
+    #[serde(default = "is_validator_default")]
+    pub is_validator: bool,
+    /// Whether the node recently sent a message, see VALIDATING_SLOT_LAG
+    #[serde(default)]
+    pub is_validating: bool,
+    /// When the node was first seen, empty as the crawler keeps no history
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub date_discovered: String,
+    /// The time of the crawl the node's data comes from
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub date_updated: String,
     pub quorum_set: QuorumSet,
-    #[serde(skip_serializing_if = "String::is_empty")]
+    /// See QuorumSet::hash_key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quorum_set_hash_key: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub isp: String,
+    #[serde(default)]
     pub geo_data: GeoData,
+    /// Every IPv4 and IPv6 address the hostname resolved to; ip, isp and geoData describe the
+    /// first of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<ResolvedAddress>,
+    /// Why the hostname couldn't be resolved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution_error: Option<String>,
+    /// The id of the organisation running the node, see MobcoinFbas::assign_organisations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
+    #[serde(default)]
+    pub statistics: NodeStatistics,
+    /// How many RPCs the crawler sent before the node replied or was given up on
+    #[serde(default)]
+    pub rpc_attempts: u32,
+    /// How long the RPC the node answered took
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_latency: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_message: Option<ScpMessage>,
+    /// Whether the latest message was signed by the node it claims to come from
+    #[serde(default)]
+    pub signature_status: SignatureStatus,
+    /// Keys other nodes list for this node that differ from the one it signs with
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "keys_to_base64",
+        deserialize_with = "keys_from_base64"
+    )]
+    pub conflicting_public_keys: Vec<Ed25519Public>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize)]
+/// The last SCP message a node sent, without the values it was voting on.
+/// It is equivalent to a crawl::ScpStatement, just encoded differently.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScpMessage {
+    /// The base64 encoded PK of the node that sent the message
+    #[serde(serialize_with = "key_to_base64", deserialize_with = "key_from_base64")]
+    pub sender: Ed25519Public,
+    pub sender_responder_id: String,
+    pub slot_index: u64,
+    pub phase: ScpPhase,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ballot_counter: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prepared_counter: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prepared_prime_counter: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_counter: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub high_counter: Option<u32>,
+    /// The base64 encoded signature
+    #[serde(
+        serialize_with = "key_to_base64",
+        deserialize_with = "bytes_from_base64"
+    )]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GeoData {
     pub country_name: String,
+    /// ISO 3166-1 alpha-2 code
+    #[serde(default)]
+    pub country_code: Option<String>,
+    /// Only known with a city database
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// Two letter code, e.g. "EU" for Europe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continent_code: Option<String>,
+    /// Only known with a city database
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// Identifies the node's ISP even if its name changes between database releases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autonomous_system_number: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autonomous_system_organization: Option<String>,
+}
+
+/// One of the addresses a node's hostname resolved to and where it is located.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedAddress {
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub isp: String,
+    #[serde(default)]
+    pub geo_data: GeoData,
+}
+
+/// The uptime statistics of stellarbeat.io.
+/// A single crawl can only tell whether a node is active in SCP, the percentages over 24 hours and
+/// 30 days need a history of crawls and are left at 0 with has24HourStats and has30DayStats unset.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStatistics {
+    pub active_in_scp: bool,
+    pub has_24_hour_stats: bool,
+    pub has_30_day_stats: bool,
+    pub active_24_hours_percentage: f64,
+    pub validating_24_hours_percentage: f64,
+    pub over_loaded_24_hours_percentage: f64,
+    pub active_30_days_percentage: f64,
+    pub validating_30_days_percentage: f64,
+    pub over_loaded_30_days_percentage: f64,
 }
 
 /// A MobcoinNode/ CrawledNode's QSet.
 /// It is equivalent to a mc_consensus_scp::QuorumSet, just encoded differently.
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct QuorumSet {
     pub threshold: u64,
@@ -42,12 +194,12 @@ pub struct QuorumSet {
 }
 
 /// The MobileCoin FBAS.
-#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize)]
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub struct MobcoinFbas(Vec<MobcoinNode>);
 
 /// The CrawlReport contains the timestamp, crawl duration, number of nodes (and number of
 /// reachable nodes) as well as the MobcoinFbas.
-#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize)]
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CrawlReport {
     /// The crawl's timestamp
@@ -56,43 +208,224 @@ pub struct CrawlReport {
     pub duration: Duration,
     /// The MobileCoin Nodes
     pub node_info: NodeInfo,
+    /// Whether the FBAS enjoys quorum intersection and how easily it is blocked or split,
+    /// missing in reports read from nodes JSONs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<AnalysisSummary>,
+    /// The GeoIP databases that geolocated the nodes, missing in reports read from nodes JSONs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub geo_ip_databases: Vec<DatabaseInfo>,
     pub nodes: MobcoinFbas,
 }
 
 /// Holds (general) data about the crawl and is included in the CrawlReport.
-#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NodeInfo {
     pub total_nodes: usize,
     pub reachable_nodes: usize,
+    /// The highest slot any node reported working on, nodes far below it are lagging
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highest_slot_index: Option<u64>,
+}
+
+/// The two JSONs the crawler writes, see CrawlReport::from_json_str.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OutputJson {
+    Report(CrawlReport),
+    Nodes(MobcoinFbas),
 }
 
 impl MobcoinFbas {
-    pub fn create_mobcoin_fbas(crawler: &Crawler) -> Self {
+    pub fn new(nodes: Vec<MobcoinNode>) -> Self {
+        Self(nodes)
+    }
+
+    pub fn nodes(&self) -> &[MobcoinNode] {
+        &self.0
+    }
+
+    /// Reads either a nodes JSON or the nodes of a crawl report JSON.
+    pub fn from_json_str(json: &str) -> Result<Self, Error> {
+        CrawlReport::from_json_str(json).map(|report| report.nodes)
+    }
+
+    /// Same as from_json_str but reads the JSON from the file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        CrawlReport::from_file(path).map(|report| report.nodes)
+    }
+
+    /// Fails if the GeoIP databases can't be opened.
+    /// Hostnames are resolved by the system's resolver.
+    pub fn create_mobcoin_fbas(crawler: &Crawler) -> Result<Self, Error> {
+        let geo_lookup = GeoLookup::open()?;
+        let resolver = CachingResolver::new(SystemResolver);
+        Ok(Self::create_mobcoin_fbas_with(
+            crawler,
+            &geo_lookup,
+            &resolver,
+        ))
+    }
+
+    /// Same as create_mobcoin_fbas but geolocates the nodes using already opened databases and
+    /// resolves their hostnames with `resolver`.
+    /// In strict mode the QSets of nodes whose messages weren't verified are left empty.
+    pub fn create_mobcoin_fbas_with(
+        crawler: &Crawler,
+        geo_lookup: &GeoLookup,
+        resolver: &dyn Resolver,
+    ) -> Self {
         let nodes = crawler
             .mobcoin_nodes
             .iter()
-            .map(|node| MobcoinNode::from_crawled_node(node.clone()))
+            .map(|node| {
+                let mut node = node.clone();
+                if crawler.config.strict && node.signature_status != SignatureStatus::Verified {
+                    node.quorum_set = McQuorumSet::empty();
+                }
+                MobcoinNode::from_crawled_node(node, &crawler.crawl_time, geo_lookup, resolver)
+            })
             .collect();
-        Self(nodes)
+        let mut fbas = Self(nodes);
+        fbas.mark_validating_nodes();
+        fbas
+    }
+
+    /// Active nodes whose latest message is at most VALIDATING_SLOT_LAG slots behind the highest
+    /// slot any node reported are validating.
+    fn mark_validating_nodes(&mut self) {
+        let highest_slot_index = CrawlReport::highest_slot_index(self);
+        for node in self.0.iter_mut() {
+            node.is_validating = match (node.latest_message.as_ref(), highest_slot_index) {
+                (Some(msg), Some(highest)) => {
+                    node.active && msg.slot_index + VALIDATING_SLOT_LAG >= highest
+                }
+                _ => false,
+            };
+            node.statistics.active_in_scp = node.is_validating;
+        }
+    }
+
+    /// Sets the organizationId of every node the `mapping` knows the organisation of and returns
+    /// these organisations in stellarbeat.io format, sorted by name.
+    /// Organisations whose names map to the same id are told apart as in organisation_ids.
+    pub fn assign_organisations(&mut self, mapping: &OrganisationMapping) -> Organisations {
+        let names: Vec<Option<String>> = self
+            .0
+            .iter()
+            .map(|node| mapping.organisation_of(&node.hostname))
+            .collect();
+        let mut organisations: BTreeMap<String, Organisation> = BTreeMap::new();
+        for (node, name) in self.0.iter().zip(names.iter()) {
+            if let Some(name) = name {
+                let organisation =
+                    organisations
+                        .entry(name.clone())
+                        .or_insert_with(|| Organisation {
+                            name: name.clone(),
+                            ..Organisation::default()
+                        });
+                if node.public_key != Ed25519Public::default() {
+                    organisation
+                        .validators
+                        .push(STANDARD.encode(node.public_key));
+                }
+            }
+        }
+        // Sorted by name so that the same organisation gets the suffix in every crawl
+        let ids = organisation_ids(organisations.keys().map(String::as_str));
+        for organisation in organisations.values_mut() {
+            organisation.id = ids[&organisation.name].clone();
+        }
+        for (node, name) in self.0.iter_mut().zip(names) {
+            node.organization_id = name.map(|name| ids[&name].clone());
+        }
+        Organisations::new(
+            organisations
+                .into_values()
+                .map(|mut organisation| {
+                    organisation.validators.sort();
+                    organisation
+                })
+                .collect(),
+        )
     }
 }
 
 impl CrawlReport {
-    pub fn create_crawl_report(fbas: MobcoinFbas, crawler: &Crawler) -> Self {
+    /// The analysis is grouped by organisation as well if `organisations` are given. Splitting
+    /// sets are only searched for if at most `max_splitting_set_candidates` nodes have a QSet, see
+    /// AnalysisSummary::with_splitting_set_limit.
+    /// `geo_ip_databases` are those the `fbas` was geolocated with, see GeoLookup::databases.
+    pub fn create_crawl_report(
+        fbas: MobcoinFbas,
+        crawler: &Crawler,
+        organisations: Option<&Organisations>,
+        max_splitting_set_candidates: usize,
+        geo_ip_databases: Vec<DatabaseInfo>,
+    ) -> Self {
         Self {
             timestamp: crawler.crawl_time.clone(),
             duration: crawler.crawl_duration,
             node_info: NodeInfo {
                 total_nodes: fbas.0.len(),
                 reachable_nodes: crawler.reachable_nodes,
+                highest_slot_index: Self::highest_slot_index(&fbas),
             },
+            analysis: Some(AnalysisSummary::with_splitting_set_limit(
+                &fbas,
+                organisations,
+                max_splitting_set_candidates,
+            )),
+            geo_ip_databases,
             nodes: fbas,
         }
     }
+
+    /// Reads a crawl report JSON.
+    /// A nodes JSON is accepted as well, the report's NodeInfo is then derived from the nodes
+    /// while its timestamp and duration are left empty.
+    pub fn from_json_str(json: &str) -> Result<Self, Error> {
+        match serde_json::from_str(json).map_err(|err| Error::Deserialise(err.to_string()))? {
+            OutputJson::Report(report) => Ok(report),
+            OutputJson::Nodes(fbas) => Ok(Self {
+                node_info: NodeInfo {
+                    total_nodes: fbas.0.len(),
+                    reachable_nodes: fbas.0.iter().filter(|node| node.active).count(),
+                    highest_slot_index: Self::highest_slot_index(&fbas),
+                },
+                nodes: fbas,
+                ..Self::default()
+            }),
+        }
+    }
+
+    /// Same as from_json_str but reads the JSON from the file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        parse_file(path, Self::from_json_str)
+    }
+
+    fn highest_slot_index(fbas: &MobcoinFbas) -> Option<u64> {
+        fbas.0
+            .iter()
+            .filter_map(|node| node.latest_message.as_ref())
+            .map(|msg| msg.slot_index)
+            .max()
+    }
 }
 
 impl QuorumSet {
+    /// Identifies equal QSets: the base64 encoded SHA-256 hash of the QSet's JSON.
+    /// None for an empty QSet, e.g. of a node that couldn't be reached.
+    pub fn hash_key(&self) -> Option<String> {
+        if self.validators.is_empty() && self.inner_quorum_sets.is_empty() {
+            return None;
+        }
+        let json = serde_json::to_vec(self).ok()?;
+        Some(STANDARD.encode(Sha256::digest(&json)))
+    }
+
     /// Converts a MobileCoin encoded QuorumSet to a Stellarbeat encoded QuorumSet
     fn from_mc_quorum_set(mc_quorum_set: McQuorumSet) -> Self {
         let threshold = mc_quorum_set.threshold.into();
@@ -116,24 +449,98 @@ impl QuorumSet {
     }
 }
 
+impl ScpMessage {
+    fn from_scp_statement(statement: ScpStatement) -> Self {
+        Self {
+            sender: statement.sender_id.public_key,
+            sender_responder_id: statement.sender_id.responder_id.to_string(),
+            slot_index: statement.slot_index,
+            phase: statement.phase,
+            ballot_counter: statement.ballot_counter,
+            prepared_counter: statement.prepared_counter,
+            prepared_prime_counter: statement.prepared_prime_counter,
+            commit_counter: statement.commit_counter,
+            high_counter: statement.high_counter,
+            signature: statement.signature,
+        }
+    }
+}
+
+impl ResolvedAddress {
+    fn locate(ip: IpAddr, geo_lookup: &GeoLookup) -> Self {
+        let record = geo_lookup.locate(ip);
+        Self {
+            ip: ip.to_string(),
+            isp: record.isp,
+            geo_data: GeoData {
+                country_name: record.country_name,
+                country_code: record.country_code,
+                latitude: record.latitude,
+                longitude: record.longitude,
+                continent_code: record.continent_code,
+                city: record.city,
+                autonomous_system_number: record.autonomous_system_number,
+                autonomous_system_organization: record.autonomous_system_organization,
+            },
+        }
+    }
+}
+
 impl MobcoinNode {
-    fn from_crawled_node(crawled_node: CrawledNode) -> Self {
+    /// Geolocation failures for this node are logged and leave the respective fields empty.
+    /// `crawl_time` is used as the node's dateUpdated.
+    fn from_crawled_node(
+        crawled_node: CrawledNode,
+        crawl_time: &str,
+        geo_lookup: &GeoLookup,
+        resolver: &dyn Resolver,
+    ) -> Self {
         let quorum_set = QuorumSet::from_mc_quorum_set(crawled_node.clone().quorum_set);
-        let ip_addr = crawled_node.resolve_hostname_to_ip();
-        let isp = DbReader::new(Database::Asn).lookup_isp(ip_addr);
-        let country_name = DbReader::new(Database::Country).lookup_country(ip_addr);
+        let (addresses, resolution_error) = match crawled_node.resolve_hostname(resolver) {
+            Ok(ips) => (
+                ips.into_iter()
+                    .map(|ip| ResolvedAddress::locate(ip, geo_lookup))
+                    .collect::<Vec<ResolvedAddress>>(),
+                None,
+            ),
+            Err(err) => {
+                warn!("{}", err);
+                (vec![], Some(err.to_string()))
+            }
+        };
+        let primary = addresses.first().cloned().unwrap_or_default();
         Self {
             public_key: crawled_node.public_key,
+            name: String::new(),
             hostname: crawled_node.domain,
+            ip: primary.ip,
             port: crawled_node.port,
             active: crawled_node.online,
+            is_validator: true,
+            is_validating: false,
+            date_discovered: String::new(),
+            date_updated: crawl_time.to_string(),
+            quorum_set_hash_key: quorum_set.hash_key(),
             quorum_set,
-            isp,
-            geo_data: GeoData { country_name },
+            isp: primary.isp,
+            geo_data: primary.geo_data,
+            addresses,
+            resolution_error,
+            organization_id: None,
+            statistics: NodeStatistics::default(),
+            rpc_attempts: crawled_node.rpc_attempts,
+            rpc_latency: crawled_node.rpc_latency,
+            latest_message: crawled_node.latest_msg.map(ScpMessage::from_scp_statement),
+            signature_status: crawled_node.signature_status,
+            conflicting_public_keys: crawled_node.conflicting_keys,
         }
     }
 }
 
+fn is_validator_default() -> bool {
+    true
+}
+
 /// Serializes `buffer` to a lowercase hex string.
 pub fn key_to_base64<T, S>(buffer: &T, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -143,10 +550,57 @@ where
     serializer.serialize_str(&STANDARD.encode(&buffer))
 }
 
+/// Deserializes a base64 string to a public key.
+pub fn key_from_base64<'de, D>(deserializer: D) -> Result<Ed25519Public, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bytes = bytes_from_base64(deserializer)?;
+    Ed25519Public::try_from(&bytes[..])
+        .map_err(|err| de::Error::custom(format!("invalid public key: {:?}", err)))
+}
+
+/// Deserializes a base64 string to its bytes.
+pub fn bytes_from_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let encoded = String::deserialize(deserializer)?;
+    STANDARD.decode(encoded).map_err(de::Error::custom)
+}
+
+/// Deserializes a list of base64 strings to public keys.
+pub fn keys_from_base64<'de, D>(deserializer: D) -> Result<Vec<Ed25519Public>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|encoded| {
+            let bytes = STANDARD.decode(encoded).map_err(de::Error::custom)?;
+            Ed25519Public::try_from(&bytes[..])
+                .map_err(|err| de::Error::custom(format!("invalid public key: {:?}", err)))
+        })
+        .collect()
+}
+
+/// Serializes each of the `keys` to a base64 string.
+pub fn keys_to_base64<S>(keys: &[Ed25519Public], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(keys.iter().map(|key| STANDARD.encode(key)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::analysis::{GroupSummary, DEFAULT_MAX_SPLITTING_SET_CANDIDATES};
+    use crate::crawl::test_utils::test_quorum_set;
+    use crate::crawl::{CrawlConfig, StaticResolver};
     use mc_consensus_scp::test_utils::test_node_id;
+    use std::collections::HashSet;
+    use std::str::FromStr;
 
     #[test]
     fn mc_qset_without_inner_to_sbeat_qset() {
@@ -244,20 +698,438 @@ mod tests {
                 ],
             ),
             online: false,
+            rpc_attempts: 1,
+            rpc_latency: None,
+            latest_msg: None,
+            signature_status: SignatureStatus::Unverified,
+            conflicting_keys: vec![],
         };
         let quorum_set = QuorumSet::from_mc_quorum_set(crawled_node.quorum_set.clone());
+        let google = IpAddr::from_str("8.8.8.8").unwrap();
+        let actual = MobcoinNode::from_crawled_node(
+            crawled_node,
+            "2022-05-01T12:00:00+00:00",
+            &GeoLookup::open().unwrap(),
+            &StaticResolver::default().with_host("test.foo.com", &[google]),
+        );
+        let geo_data = GeoData {
+            country_name: String::from("United States"),
+            country_code: Some(String::from("US")),
+            latitude: None,
+            longitude: None,
+            continent_code: Some(String::from("NA")),
+            city: None,
+            autonomous_system_number: Some(15169),
+            autonomous_system_organization: Some(String::from("GOOGLE")),
+        };
         let expected = MobcoinNode {
             public_key: Ed25519Public::default(),
+            name: String::new(),
             hostname: "test.foo.com".to_string(),
+            ip: String::from("8.8.8.8"),
             port: 443,
-            quorum_set,
             active: false,
-            isp: String::from(""),
-            geo_data: GeoData {
-                country_name: String::from("United States"),
-            },
+            is_validator: true,
+            is_validating: false,
+            date_discovered: String::new(),
+            date_updated: String::from("2022-05-01T12:00:00+00:00"),
+            quorum_set_hash_key: quorum_set.hash_key(),
+            quorum_set,
+            isp: String::from("GOOGLE"),
+            geo_data: geo_data.clone(),
+            addresses: vec![ResolvedAddress {
+                ip: String::from("8.8.8.8"),
+                isp: String::from("GOOGLE"),
+                geo_data,
+            }],
+            resolution_error: None,
+            organization_id: None,
+            statistics: NodeStatistics::default(),
+            rpc_attempts: 1,
+            rpc_latency: None,
+            latest_message: None,
+            signature_status: SignatureStatus::Unverified,
+            conflicting_public_keys: vec![],
         };
-        let actual = MobcoinNode::from_crawled_node(crawled_node);
         assert_eq!(expected, actual);
+        assert!(expected.quorum_set_hash_key.is_some());
+    }
+
+    #[test]
+    fn nodes_without_is_validator_are_validators() {
+        let node = MobcoinNode {
+            public_key: test_node_id(0).public_key,
+            hostname: "node0.test.com".to_string(),
+            is_validator: true,
+            ..Default::default()
+        };
+        let mut json = serde_json::to_value(&node).unwrap();
+        assert!(json.get("name").is_none());
+        assert!(json.get("dateDiscovered").is_none());
+        json.as_object_mut().unwrap().remove("isValidator");
+        let actual: MobcoinNode = serde_json::from_value(json).unwrap();
+        assert!(actual.is_validator);
+    }
+
+    #[test]
+    fn strict_fbas_leaves_out_unverified_quorum_sets() {
+        let mut verified = CrawledNode::new(
+            String::from("mc://127.0.0.1:443"),
+            true,
+            test_quorum_set(&[0]),
+        )
+        .unwrap();
+        verified.signature_status = SignatureStatus::Verified;
+        let unverified = CrawledNode::new(
+            String::from("mc://127.0.0.2:443"),
+            true,
+            test_quorum_set(&[0]),
+        )
+        .unwrap();
+        let fbas = |strict: bool| {
+            let mut crawler = Crawler::default().with_config(CrawlConfig {
+                strict,
+                ..CrawlConfig::default()
+            });
+            crawler.mobcoin_nodes = HashSet::from([verified.clone(), unverified.clone()]);
+            MobcoinFbas::create_mobcoin_fbas_with(
+                &crawler,
+                &GeoLookup::open().unwrap(),
+                &StaticResolver::default(),
+            )
+        };
+        let empty_quorum_sets = |fbas: MobcoinFbas| {
+            fbas.nodes()
+                .iter()
+                .filter(|node| node.quorum_set.validators.is_empty())
+                .map(|node| node.ip.clone())
+                .collect::<Vec<String>>()
+        };
+        assert!(empty_quorum_sets(fbas(false)).is_empty());
+        assert_eq!(
+            empty_quorum_sets(fbas(true)),
+            vec![String::from("127.0.0.2")]
+        );
+    }
+
+    #[test]
+    fn unresolvable_hostnames_are_reported() {
+        let crawled_node = CrawledNode::new(
+            String::from("mc://does-not-exist.invalid:443"),
+            false,
+            McQuorumSet::empty(),
+        )
+        .unwrap();
+        let actual = MobcoinNode::from_crawled_node(
+            crawled_node,
+            "2022-05-01T12:00:00+00:00",
+            &GeoLookup::open().unwrap(),
+            &StaticResolver::default(),
+        );
+        assert!(actual.ip.is_empty());
+        assert!(actual.addresses.is_empty());
+        assert_eq!(actual.geo_data, GeoData::default());
+        assert!(matches!(actual.resolution_error, Some(err) if err.contains("does-not-exist")));
+    }
+
+    #[test]
+    fn scp_statement_to_scp_message() {
+        let sender_id = test_node_id(0);
+        let statement = ScpStatement {
+            sender_id: sender_id.clone(),
+            slot_index: 7,
+            phase: ScpPhase::Commit,
+            ballot_counter: Some(2),
+            prepared_counter: Some(2),
+            prepared_prime_counter: None,
+            commit_counter: Some(1),
+            high_counter: Some(2),
+            signature: vec![1, 2, 3],
+        };
+        let actual = ScpMessage::from_scp_statement(statement);
+        assert_eq!(actual.sender, sender_id.public_key);
+        assert_eq!(
+            actual.sender_responder_id,
+            sender_id.responder_id.to_string()
+        );
+        let json = serde_json::to_value(&actual).unwrap();
+        assert_eq!(json["phase"], "commit");
+        assert_eq!(json["slotIndex"], 7);
+        assert_eq!(json["signature"], "AQID");
+        assert!(json.get("preparedPrimeCounter").is_none());
+    }
+
+    #[test]
+    fn crawl_report_round_trip() {
+        let node = MobcoinNode {
+            public_key: test_node_id(0).public_key,
+            hostname: "node0.test.com".to_string(),
+            port: 443,
+            active: true,
+            quorum_set: QuorumSet::from_mc_quorum_set(McQuorumSet::new(
+                1,
+                vec![QuorumSetMember::Node(test_node_id(1))],
+            )),
+            isp: String::from("Test ISP"),
+            addresses: vec![
+                ResolvedAddress {
+                    ip: String::from("10.0.0.1"),
+                    isp: String::from("Test ISP"),
+                    ..ResolvedAddress::default()
+                },
+                ResolvedAddress {
+                    ip: String::from("2001:db8::1"),
+                    isp: String::from("Other ISP"),
+                    ..ResolvedAddress::default()
+                },
+            ],
+            rpc_attempts: 2,
+            rpc_latency: Some(Duration::from_millis(20)),
+            latest_message: Some(ScpMessage {
+                sender: test_node_id(0).public_key,
+                sender_responder_id: test_node_id(0).responder_id.to_string(),
+                slot_index: 3,
+                phase: ScpPhase::Externalize,
+                ballot_counter: Some(1),
+                prepared_counter: None,
+                prepared_prime_counter: None,
+                commit_counter: Some(1),
+                high_counter: Some(1),
+                signature: vec![7; 64],
+            }),
+            signature_status: SignatureStatus::Invalid,
+            conflicting_public_keys: vec![test_node_id(2).public_key],
+            ..MobcoinNode::default()
+        };
+        let report = CrawlReport {
+            timestamp: String::from("2022-05-01T12:00:00+00:00"),
+            duration: Duration::new(1, 5),
+            node_info: NodeInfo {
+                total_nodes: 1,
+                reachable_nodes: 1,
+                highest_slot_index: Some(3),
+            },
+            analysis: Some(AnalysisSummary {
+                has_quorum_intersection: true,
+                minimal_quorums: 1,
+                smallest_blocking_set_size: 1,
+                smallest_splitting_set_size: None,
+                by_isp: Some(GroupSummary {
+                    smallest_blocking_set_size: 1,
+                    smallest_splitting_set_size: None,
+                }),
+                ..AnalysisSummary::default()
+            }),
+            geo_ip_databases: vec![DatabaseInfo {
+                database_type: String::from("GeoLite2-ASN"),
+                build_epoch: 1627326000,
+            }],
+            nodes: MobcoinFbas::new(vec![node]),
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(CrawlReport::from_json_str(&json).unwrap(), report);
+        let nodes_json = serde_json::to_string(&report.nodes).unwrap();
+        assert_eq!(
+            MobcoinFbas::from_json_str(&nodes_json).unwrap(),
+            report.nodes
+        );
+        // A nodes JSON makes a report without timestamp
+        let from_nodes = CrawlReport::from_json_str(&nodes_json).unwrap();
+        assert_eq!(from_nodes.node_info, report.node_info);
+        assert!(from_nodes.timestamp.is_empty());
+        assert!(from_nodes.geo_ip_databases.is_empty());
+        assert_eq!(from_nodes.analysis, None);
+    }
+
+    #[test]
+    fn equal_quorum_sets_have_equal_hash_keys() {
+        let quorum_set = |threshold| QuorumSet {
+            threshold,
+            validators: vec![STANDARD.encode(test_node_id(0).public_key)],
+            inner_quorum_sets: vec![],
+        };
+        assert_eq!(quorum_set(1).hash_key(), quorum_set(1).hash_key());
+        assert_ne!(quorum_set(1).hash_key(), quorum_set(2).hash_key());
+        assert_eq!(QuorumSet::default().hash_key(), None);
+    }
+
+    #[test]
+    fn lagging_nodes_are_not_validating() {
+        let node = |index: u32, active: bool, slot_index: u64| MobcoinNode {
+            public_key: test_node_id(index).public_key,
+            active,
+            latest_message: Some(ScpMessage {
+                sender: test_node_id(index).public_key,
+                sender_responder_id: String::default(),
+                slot_index,
+                phase: ScpPhase::Externalize,
+                ballot_counter: None,
+                prepared_counter: None,
+                prepared_prime_counter: None,
+                commit_counter: None,
+                high_counter: None,
+                signature: vec![],
+            }),
+            ..MobcoinNode::default()
+        };
+        let mut fbas = MobcoinFbas::new(vec![
+            node(0, true, 100),
+            node(1, true, 100 - VALIDATING_SLOT_LAG),
+            node(2, true, 99 - VALIDATING_SLOT_LAG),
+            node(3, false, 100),
+            MobcoinNode::default(),
+        ]);
+        fbas.mark_validating_nodes();
+        let validating: Vec<bool> = fbas.nodes().iter().map(|node| node.is_validating).collect();
+        assert_eq!(validating, vec![true, true, false, false, false]);
+        assert!(fbas.nodes()[0].statistics.active_in_scp);
+    }
+
+    #[test]
+    fn organisations_are_assigned() {
+        let node = |index: u32, hostname: &str| MobcoinNode {
+            public_key: test_node_id(index).public_key,
+            hostname: hostname.to_string(),
+            ..MobcoinNode::default()
+        };
+        let mut fbas = MobcoinFbas::new(vec![
+            node(0, "peer2.prod.mobilecoinww.com"),
+            node(1, "peer1.prod.mobilecoinww.com"),
+            node(2, "binance.mobilecoin.bdnodes.net"),
+            node(3, "127.0.0.1"),
+        ]);
+        let mapping =
+            OrganisationMapping::from_json_str(r#"{"bdnodes.net": "Blockdaemon"}"#, true).unwrap();
+        let organisations = fbas.assign_organisations(&mapping);
+        let ids: Vec<Option<&str>> = fbas
+            .nodes()
+            .iter()
+            .map(|node| node.organization_id.as_deref())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                Some("mobilecoinww.com"),
+                Some("mobilecoinww.com"),
+                Some("blockdaemon"),
+                None
+            ]
+        );
+        let mut mobilecoin_validators = vec![
+            STANDARD.encode(test_node_id(0).public_key),
+            STANDARD.encode(test_node_id(1).public_key),
+        ];
+        mobilecoin_validators.sort();
+        assert_eq!(
+            organisations,
+            Organisations::new(vec![
+                Organisation {
+                    id: String::from("blockdaemon"),
+                    name: String::from("Blockdaemon"),
+                    validators: vec![STANDARD.encode(test_node_id(2).public_key)],
+                },
+                Organisation {
+                    id: String::from("mobilecoinww.com"),
+                    name: String::from("mobilecoinww.com"),
+                    validators: mobilecoin_validators,
+                },
+            ])
+        );
+        let json = serde_json::to_value(&fbas).unwrap();
+        assert_eq!(json[0]["organizationId"], "mobilecoinww.com");
+        assert!(json[3].get("organizationId").is_none());
+    }
+
+    #[test]
+    fn colliding_organisations_are_kept_apart() {
+        let node = |index: u32| MobcoinNode {
+            public_key: test_node_id(index).public_key,
+            hostname: format!("node{}.test.com", index),
+            ..MobcoinNode::default()
+        };
+        let mut fbas = MobcoinFbas::new(vec![node(0), node(1)]);
+        let mapping = OrganisationMapping::from_json_str(
+            r#"{"node0.test.com": "A B", "node1.test.com": "a-b"}"#,
+            false,
+        )
+        .unwrap();
+        let organisations = fbas.assign_organisations(&mapping);
+        assert_eq!(organisations.organisations().len(), 2);
+        assert_eq!(fbas.nodes()[0].organization_id.as_deref(), Some("a-b"));
+        assert_eq!(fbas.nodes()[1].organization_id.as_deref(), Some("a-b-2"));
+    }
+
+    #[test]
+    fn crawl_report_records_analysis() {
+        let quorum_set = QuorumSet {
+            threshold: 1,
+            validators: vec![STANDARD.encode(test_node_id(0).public_key)],
+            inner_quorum_sets: vec![],
+        };
+        let fbas = MobcoinFbas::new(vec![MobcoinNode {
+            public_key: test_node_id(0).public_key,
+            active: true,
+            quorum_set,
+            ..MobcoinNode::default()
+        }]);
+        let report = CrawlReport::create_crawl_report(
+            fbas,
+            &Crawler::new(vec![]),
+            None,
+            DEFAULT_MAX_SPLITTING_SET_CANDIDATES,
+            vec![],
+        );
+        assert_eq!(
+            report.analysis,
+            Some(AnalysisSummary {
+                has_quorum_intersection: true,
+                minimal_quorums: 1,
+                smallest_blocking_set_size: 1,
+                smallest_splitting_set_size: None,
+                ..AnalysisSummary::default()
+            })
+        );
+    }
+
+    #[test]
+    fn historical_crawl_data_can_be_loaded() {
+        let report = CrawlReport::from_file(Path::new(
+            "crawl_data/mobilecoin_crawl_report_completed_manually_2021-08-23.json",
+        ))
+        .unwrap();
+        assert_eq!(report.node_info.total_nodes, report.nodes.nodes().len());
+        assert_eq!(report.duration, Duration::new(1, 884407016));
+        let fbas = MobcoinFbas::from_file(Path::new(
+            "crawl_data/mobilecoin_nodes_completed_manually_2021-08-02.json",
+        ))
+        .unwrap();
+        assert_eq!(fbas.nodes().len(), 10);
+        assert!(fbas
+            .nodes()
+            .iter()
+            .all(|node| node.public_key != Ed25519Public::default()));
+    }
+
+    #[test]
+    fn invalid_public_key_is_rejected() {
+        let json = r#"[{"publicKey": "AQID", "hostname": "node0.test.com", "port": 443,
+            "active": true, "quorumSet": {"threshold": 0, "validators": []}}]"#;
+        let actual = MobcoinFbas::from_json_str(json);
+        assert!(matches!(actual, Err(Error::Deserialise(_))));
+    }
+
+    #[test]
+    fn file_errors_name_the_file_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        assert!(matches!(CrawlReport::from_file(&path), Err(Error::Io(_))));
+        std::fs::write(&path, "{").unwrap();
+        let actual = CrawlReport::from_file(&path).unwrap_err();
+        let expected_prefix = format!("{}: ", path.display());
+        assert!(matches!(&actual, Error::Deserialise(msg) if msg.starts_with(&expected_prefix)));
+        assert_eq!(
+            actual.to_string().matches("Deserialisation failed").count(),
+            1
+        );
     }
 }
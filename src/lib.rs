@@ -1,10 +1,16 @@
 #[macro_use]
 extern crate log;
 
+pub mod analysis;
+pub mod api;
 pub mod crawl;
+pub mod error;
 pub mod io;
 pub mod stats;
 
+pub use analysis::*;
+pub use api::*;
 pub use crawl::*;
+pub use error::*;
 pub use io::*;
 pub use stats::*;
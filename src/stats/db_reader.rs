@@ -1,69 +1,288 @@
-use log::debug;
-use maxminddb::{geoip2, MaxMindDBError};
+use crate::error::Error;
+use crate::io::parse_file;
+
+use log::{debug, warn};
+use maxminddb::geoip2;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 static ISP_DB_PATH: &str = "./src/stats/geolite2_dbs/GeoLite2-ASN_20210727/GeoLite2-ASN.mmdb";
 
 static COUNTRY_DB_PATH: &str =
     "./src/stats/geolite2_dbs/GeoLite2-Country_20210727/GeoLite2-Country.mmdb";
 
+/// Environment variable with the path to the ISP database, see GeoIpConfig::from_env.
+pub static ISP_DB_ENV: &str = "MC_CRAWLER_ISP_DB";
+
+/// Environment variable with the path to the country database, see GeoIpConfig::from_env.
+pub static COUNTRY_DB_ENV: &str = "MC_CRAWLER_COUNTRY_DB";
+
 pub struct DbReader {
     reader: maxminddb::Reader<Vec<u8>>,
 }
 
+/// The ISP and country databases, opened once so that they can be shared by all lookups of a
+/// report or, in daemon mode, of every crawl.
+pub struct GeoLookup {
+    isp: DbReader,
+    country: DbReader,
+}
+
+/// What the databases know about an IP address, unknown fields are left empty.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct GeoRecord {
+    /// The ISP's name or, with a GeoLite2-ASN database, the autonomous system's organisation
+    pub isp: String,
+    /// Unlike the names, AS numbers stay the same across database releases
+    pub autonomous_system_number: Option<u32>,
+    pub autonomous_system_organization: Option<String>,
+    pub country_name: String,
+    /// ISO 3166-1 alpha-2 code
+    pub country_code: Option<String>,
+    /// Two letter code, e.g. "EU" for Europe
+    pub continent_code: Option<String>,
+    /// Only known with a city database
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// Where the GeoIP databases are read from, the bundled 2021 GeoLite2 snapshots by default.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GeoIpConfig {
+    /// A GeoLite2-ASN or GeoIP2-ISP database
+    pub isp_db: PathBuf,
+    /// A GeoLite2-Country, GeoLite2-City, GeoIP2-Country or GeoIP2-City database
+    pub country_db: PathBuf,
+}
+
+/// Identifies the snapshot of a database, recorded in every CrawlReport.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseInfo {
+    /// E.g. "GeoLite2-ASN" or "GeoIP2-City"
+    pub database_type: String,
+    /// When the database was built, in seconds since the Unix epoch
+    pub build_epoch: u64,
+}
+
 #[derive(Debug, Clone)]
 pub enum Database {
     Asn,
     Country,
 }
 
+impl Default for GeoIpConfig {
+    fn default() -> Self {
+        Self {
+            isp_db: PathBuf::from(ISP_DB_PATH),
+            country_db: PathBuf::from(COUNTRY_DB_PATH),
+        }
+    }
+}
+
+impl GeoIpConfig {
+    /// The default paths unless they are overridden by ISP_DB_ENV or COUNTRY_DB_ENV.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            isp_db: env::var_os(ISP_DB_ENV).map_or(default.isp_db, PathBuf::from),
+            country_db: env::var_os(COUNTRY_DB_ENV).map_or(default.country_db, PathBuf::from),
+        }
+    }
+
+    /// Reads a JSON object with the "ispDb" and/or "countryDb" path, missing paths are the
+    /// defaults.
+    pub fn from_json_str(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|err| Error::Deserialise(err.to_string()))
+    }
+
+    /// Same as from_json_str but reads the JSON from the file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        parse_file(path, Self::from_json_str)
+    }
+}
+
 impl DbReader {
-    pub fn new(db: Database) -> Self {
-        let path = match db {
-            Database::Asn => ISP_DB_PATH,
-            Database::Country => COUNTRY_DB_PATH,
-        };
-        let reader = maxminddb::Reader::open_readfile(path).expect("Error opening database");
-        debug!("Succesfully opened {:?} database", db);
-        DbReader { reader }
-    }
-
-    pub fn lookup_country(&self, ip: IpAddr) -> String {
-        let country: Result<geoip2::Country, MaxMindDBError> = self.reader.lookup(ip);
-        match country {
-            Ok(country_data) => {
-                let country_map = country_data.country.and_then(|cy| cy.names);
-                if let Some(map) = country_map {
-                    map.get("en").unwrap().to_string()
-                } else {
-                    warn!("EN entry unavailable.");
-                    "".to_string()
-                }
-            }
-            Err(err) => {
-                warn!("Country lookup for {} failed: {}", ip, err);
-                "".to_string()
-            }
+    /// Opens the bundled database.
+    pub fn new(db: Database) -> Result<Self, Error> {
+        let config = GeoIpConfig::default();
+        match db {
+            Database::Asn => Self::open(&config.isp_db),
+            Database::Country => Self::open(&config.country_db),
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let reader = maxminddb::Reader::open_readfile(path).map_err(|err| {
+            Error::GeoIp(format!(
+                "Error opening database {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        debug!(
+            "Succesfully opened {} database {}",
+            reader.metadata.database_type,
+            path.display()
+        );
+        Ok(DbReader { reader })
+    }
+
+    pub fn info(&self) -> DatabaseInfo {
+        DatabaseInfo {
+            database_type: self.reader.metadata.database_type.clone(),
+            build_epoch: self.reader.metadata.build_epoch,
+        }
+    }
+
+    /// Fails unless the database's type contains one of `types`, e.g. "City" for
+    /// "GeoLite2-City".
+    fn expect_type(self, types: &[&str]) -> Result<Self, Error> {
+        let database_type = &self.reader.metadata.database_type;
+        if types
+            .iter()
+            .any(|expected| database_type.contains(expected))
+        {
+            Ok(self)
+        } else {
+            Err(Error::GeoIp(format!(
+                "Expected a {} database but got {}",
+                types.join(" or "),
+                database_type
+            )))
         }
     }
 
-    pub fn lookup_isp(&self, ip: IpAddr) -> String {
-        let isp: Result<geoip2::Isp, MaxMindDBError> = self.reader.lookup(ip);
-        match isp {
-            Ok(isp_info) => {
-                if let Some(isp_name) = isp_info.isp {
-                    isp_name.to_string()
-                } else {
-                    warn!("No ISP name entry found for {} in database.", ip);
-                    "".to_string()
-                }
-            }
-            Err(err) => {
-                warn!("ISP lookup for {} failed: {}", ip, err);
-                "".to_string()
-            }
+    pub fn lookup_country(&self, ip: IpAddr) -> Result<String, Error> {
+        let country: geoip2::Country = self.reader.lookup(ip)?;
+        country
+            .country
+            .and_then(|cy| cy.names)
+            .and_then(|names| names.get("en").map(|name| name.to_string()))
+            .ok_or_else(|| Error::GeoIp(format!("No EN country name found for {}", ip)))
+    }
+
+    pub fn lookup_country_code(&self, ip: IpAddr) -> Result<String, Error> {
+        let country: geoip2::Country = self.reader.lookup(ip)?;
+        country
+            .country
+            .and_then(|cy| cy.iso_code)
+            .map(|code| code.to_string())
+            .ok_or_else(|| Error::GeoIp(format!("No ISO country code found for {}", ip)))
+    }
+
+    /// The ISP's name from a GeoIP2-ISP database or the autonomous system's organisation from a
+    /// GeoLite2-ASN database.
+    pub fn lookup_isp(&self, ip: IpAddr) -> Result<String, Error> {
+        let isp: geoip2::Isp = self.reader.lookup(ip)?;
+        isp.isp
+            .or(isp.autonomous_system_organization)
+            .map(|isp_name| isp_name.to_string())
+            .ok_or_else(|| Error::GeoIp(format!("No ISP name entry found for {}", ip)))
+    }
+
+    /// The ISP and autonomous system of `ip`.
+    pub fn lookup_network(&self, ip: IpAddr) -> Result<GeoRecord, Error> {
+        let isp: geoip2::Isp = self.reader.lookup(ip)?;
+        Ok(GeoRecord {
+            isp: isp
+                .isp
+                .or(isp.autonomous_system_organization)
+                .map(String::from)
+                .unwrap_or_default(),
+            autonomous_system_number: isp.autonomous_system_number,
+            autonomous_system_organization: isp.autonomous_system_organization.map(String::from),
+            ..GeoRecord::default()
+        })
+    }
+
+    /// The country and continent of `ip` and, with a city database, its city and coordinates.
+    pub fn lookup_location(&self, ip: IpAddr) -> Result<GeoRecord, Error> {
+        // Country databases simply lack the city's fields
+        let city: geoip2::City = self.reader.lookup(ip)?;
+        let (country_name, country_code) = match city.country {
+            Some(country) => (
+                english_name(country.names).unwrap_or_default(),
+                country.iso_code.map(String::from),
+            ),
+            None => (String::default(), None),
+        };
+        let (latitude, longitude) = city.location.map_or((None, None), |location| {
+            (location.latitude, location.longitude)
+        });
+        Ok(GeoRecord {
+            country_name,
+            country_code,
+            continent_code: city
+                .continent
+                .and_then(|continent| continent.code)
+                .map(String::from),
+            city: city.city.and_then(|city| english_name(city.names)),
+            latitude,
+            longitude,
+            ..GeoRecord::default()
+        })
+    }
+}
+
+impl GeoLookup {
+    /// Opens the databases configured in the environment, see GeoIpConfig::from_env.
+    pub fn open() -> Result<Self, Error> {
+        Self::open_with(&GeoIpConfig::from_env())
+    }
+
+    /// Fails if either database can't be opened or is of the wrong type.
+    pub fn open_with(config: &GeoIpConfig) -> Result<Self, Error> {
+        Ok(GeoLookup {
+            isp: DbReader::open(&config.isp_db)?.expect_type(&["ASN", "ISP"])?,
+            country: DbReader::open(&config.country_db)?.expect_type(&["Country", "City"])?,
+        })
+    }
+
+    /// The ISP and country databases' types and build epochs.
+    pub fn databases(&self) -> Vec<DatabaseInfo> {
+        vec![self.isp.info(), self.country.info()]
+    }
+
+    /// Looks up `ip` in both databases.
+    /// Failed lookups are logged and leave the respective fields empty.
+    pub fn locate(&self, ip: IpAddr) -> GeoRecord {
+        let network = self.isp.lookup_network(ip).unwrap_or_else(|err| {
+            warn!("No ISP found for {}: {}", ip, err);
+            GeoRecord::default()
+        });
+        let location = self.country.lookup_location(ip).unwrap_or_else(|err| {
+            warn!("No location found for {}: {}", ip, err);
+            GeoRecord::default()
+        });
+        GeoRecord {
+            isp: network.isp,
+            autonomous_system_number: network.autonomous_system_number,
+            autonomous_system_organization: network.autonomous_system_organization,
+            ..location
         }
     }
+
+    pub fn lookup_country(&self, ip: IpAddr) -> Result<String, Error> {
+        self.country.lookup_country(ip)
+    }
+
+    pub fn lookup_country_code(&self, ip: IpAddr) -> Result<String, Error> {
+        self.country.lookup_country_code(ip)
+    }
+
+    pub fn lookup_isp(&self, ip: IpAddr) -> Result<String, Error> {
+        self.isp.lookup_isp(ip)
+    }
+}
+
+fn english_name(names: Option<BTreeMap<&str, &str>>) -> Option<String> {
+    names.and_then(|names| names.get("en").map(|name| name.to_string()))
 }
 
 #[cfg(test)]
@@ -73,28 +292,99 @@ mod tests {
 
     #[test]
     fn valid_ip_country_lookup() {
-        let db_reader = DbReader::new(Database::Country);
+        let db_reader = DbReader::new(Database::Country).unwrap();
         let google: IpAddr = FromStr::from_str("8.8.8.8").unwrap();
-        let actual = db_reader.lookup_country(google);
+        let actual = db_reader.lookup_country(google).unwrap();
         let expected = String::from("United States");
         assert_eq!(actual, expected);
+        let actual = db_reader.lookup_country_code(google).unwrap();
+        assert_eq!(actual, "US");
     }
 
     #[test]
     fn invalid_ip_country_lookup() {
-        let db_reader = DbReader::new(Database::Country);
+        let db_reader = DbReader::new(Database::Country).unwrap();
         let zero_addr: IpAddr = FromStr::from_str("0.0.0.0").unwrap();
         let actual = db_reader.lookup_country(zero_addr);
-        let expected = String::from("");
-        assert_eq!(actual, expected);
+        assert!(matches!(actual, Err(Error::GeoIp(_))));
+    }
+
+    #[test]
+    fn geo_lookup_uses_both_databases() {
+        let lookup = GeoLookup::open().unwrap();
+        let google: IpAddr = FromStr::from_str("8.8.8.8").unwrap();
+        let actual = lookup.locate(google);
+        assert_eq!(actual.country_name, "United States");
+        assert_eq!(actual.country_code, Some(String::from("US")));
+        let zero_addr: IpAddr = FromStr::from_str("0.0.0.0").unwrap();
+        assert!(matches!(lookup.lookup_isp(zero_addr), Err(Error::GeoIp(_))));
+        assert_eq!(lookup.locate(zero_addr), GeoRecord::default());
+    }
+
+    #[test]
+    fn geo_records_combine_both_databases() {
+        let lookup = GeoLookup::open_with(&GeoIpConfig::default()).unwrap();
+        let google: IpAddr = FromStr::from_str("8.8.8.8").unwrap();
+        let actual = lookup.locate(google);
+        assert_eq!(actual.autonomous_system_number, Some(15169));
+        assert_eq!(
+            actual.autonomous_system_organization,
+            Some(String::from("GOOGLE"))
+        );
+        // GeoLite2-ASN has no ISP names
+        assert_eq!(actual.isp, "GOOGLE");
+        assert_eq!(actual.country_name, "United States");
+        assert_eq!(actual.country_code, Some(String::from("US")));
+        assert_eq!(actual.continent_code, Some(String::from("NA")));
+        // Only a city database knows these
+        assert_eq!(actual.city, None);
+        assert_eq!(actual.latitude, None);
+    }
+
+    #[test]
+    fn database_info_is_read_from_metadata() {
+        let lookup = GeoLookup::open_with(&GeoIpConfig::default()).unwrap();
+        let databases = lookup.databases();
+        assert_eq!(databases[0].database_type, "GeoLite2-ASN");
+        assert_eq!(databases[1].database_type, "GeoLite2-Country");
+        // The bundled snapshots are from July 2021
+        assert!(databases
+            .iter()
+            .all(|db| (1_625_097_600..1_627_776_000).contains(&db.build_epoch)));
+    }
+
+    #[test]
+    fn databases_of_the_wrong_type_are_rejected() {
+        let config = GeoIpConfig {
+            isp_db: PathBuf::from(COUNTRY_DB_PATH),
+            ..GeoIpConfig::default()
+        };
+        assert!(matches!(
+            GeoLookup::open_with(&config),
+            Err(Error::GeoIp(_))
+        ));
+        let missing = GeoIpConfig {
+            country_db: PathBuf::from("missing.mmdb"),
+            ..GeoIpConfig::default()
+        };
+        assert!(matches!(
+            GeoLookup::open_with(&missing),
+            Err(Error::GeoIp(_))
+        ));
+    }
+
+    #[test]
+    fn config_paths_default_to_bundled_databases() {
+        let actual = GeoIpConfig::from_json_str(r#"{"countryDb": "GeoLite2-City.mmdb"}"#).unwrap();
+        assert_eq!(actual.isp_db, PathBuf::from(ISP_DB_PATH));
+        assert_eq!(actual.country_db, PathBuf::from("GeoLite2-City.mmdb"));
     }
 
     #[test]
     fn invalid_ip_isp_lookup() {
-        let db_reader = DbReader::new(Database::Asn);
+        let db_reader = DbReader::new(Database::Asn).unwrap();
         let zero_addr: IpAddr = FromStr::from_str("0.0.0.0").unwrap();
         let actual = db_reader.lookup_isp(zero_addr);
-        let expected = String::from("");
-        assert_eq!(actual, expected);
+        assert!(matches!(actual, Err(Error::GeoIp(_))));
     }
 }
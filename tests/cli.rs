@@ -17,3 +17,118 @@ fn invalid_bootstrap_file_passed() -> Result<(), Box<dyn std::error::Error>> {
         .stderr(predicate::str::contains("Error opening bootstrap file"));
     Ok(())
 }
+
+#[test]
+fn invalid_durations_passed() -> Result<(), Box<dyn std::error::Error>> {
+    for (flag, value) in [
+        ("--connect-timeout", "-1"),
+        ("--rpc-deadline", "NaN"),
+        ("--retry-backoff", "inf"),
+        ("--checkpoint-interval", "1e30"),
+        ("--interval", "soon"),
+    ] {
+        let mut cmd = Command::cargo_bin("mc-crawler")?;
+        // "=" keeps "-1" from being taken for a flag
+        cmd.arg(format!("{}={}", flag, value));
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains(value));
+    }
+    Ok(())
+}
+
+#[test]
+fn unwritable_output_fails() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bootstrap = dir.path().join("bootstrap.txt");
+    std::fs::write(&bootstrap, "")?;
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    // A file can't be the parent of the output directory
+    cmd.arg("--fbas")
+        .arg("--output")
+        .arg(bootstrap.join("output"))
+        .arg(&bootstrap);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error writing output files"));
+    Ok(())
+}
+
+#[test]
+fn serve_with_invalid_address() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&["serve", "--listen", "not-an-address"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error starting HTTP server"));
+    Ok(())
+}
+
+#[test]
+fn diff_of_identical_crawls() -> Result<(), Box<dyn std::error::Error>> {
+    let report = "crawl_data/mobilecoin_crawl_report_completed_manually_2021-08-23.json";
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&["diff", report, report]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("No changes."));
+    Ok(())
+}
+
+#[test]
+fn diff_as_json() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&[
+        "diff",
+        "crawl_data/mobilecoin_nodes_completed_manually_2021-08-02.json",
+        "crawl_data/mobilecoin_crawl_report_completed_manually_2021-08-23.json",
+        "--json",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"changes\": ["));
+    Ok(())
+}
+
+#[test]
+fn invalid_organisations_file_passed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&["--organisations", "./invalid-file"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error reading organisations file"));
+    Ok(())
+}
+
+#[test]
+fn org_mapping_only_requires_mapping() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.arg("--org-mapping-only");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn invalid_geoip_database_passed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&["--fbas", "--country-db", "./invalid-file"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error opening GeoIP databases"));
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&["--fbas", "--geoip-config", "./invalid-file"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error reading GeoIP config"));
+    Ok(())
+}
+
+#[test]
+fn invalid_hosts_file_passed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&["--hosts", "./invalid-file"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Error reading hosts file"));
+    Ok(())
+}
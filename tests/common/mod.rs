@@ -0,0 +1,171 @@
+//! Stand-in MobileCoin validators for integration tests.
+//!
+//! Every TestPeer runs a ConsensusPeerApi gRPC server on loopback which answers get_latest_msg
+//! with whatever ConsensusMsg it was configured with. A TestNetwork bundles these peers so the
+//! mc-crawler binary can crawl them and its output can be compared against golden files.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use grpcio::{EnvBuilder, RpcContext, Server, ServerBuilder, UnarySink};
+use serde_json::Value;
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use mc_common::{NodeID, ResponderId};
+use mc_consensus_api::{
+    consensus_peer::GetLatestMsgResponse,
+    consensus_peer_grpc::{create_consensus_peer_api, ConsensusPeerApi},
+    empty::Empty,
+};
+use mc_consensus_scp::{
+    core_types::Ballot,
+    msg::{ExternalizePayload, Msg, Topic},
+    test_utils::test_node_id,
+    QuorumSet,
+};
+use mc_crypto_keys::Ed25519Signature;
+use mc_peers::ConsensusMsg;
+use mc_util_serial::serialize;
+
+/// Answers get_latest_msg with the configured payload, all other calls are unimplemented.
+#[derive(Clone, Default)]
+struct PeerService {
+    payload: Arc<Mutex<Vec<u8>>>,
+}
+
+impl ConsensusPeerApi for PeerService {
+    fn get_latest_msg(
+        &mut self,
+        ctx: RpcContext,
+        _req: Empty,
+        sink: UnarySink<GetLatestMsgResponse>,
+    ) {
+        let mut response = GetLatestMsgResponse::new();
+        response.set_payload(self.payload.lock().unwrap().clone());
+        ctx.spawn(async move {
+            let _ = sink.success(response).await;
+        });
+    }
+}
+
+/// A single stand-in validator listening on 127.0.0.1.
+pub struct TestPeer {
+    _server: Server,
+    service: PeerService,
+    pub port: u16,
+}
+
+/// A synthetic network of running TestPeers and offline nodes that are only referenced by
+/// quorum sets. Node `i` uses the public key of test_node_id(i).
+pub struct TestNetwork {
+    pub peers: Vec<TestPeer>,
+    /// Ports of all nodes, running peers first
+    pub ports: Vec<u16>,
+}
+
+impl TestPeer {
+    pub fn start() -> Self {
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = PeerService::default();
+        let mut server = ServerBuilder::new(env)
+            .register_service(create_consensus_peer_api(service.clone()))
+            .bind("127.0.0.1", 0)
+            .build()
+            .expect("Error building test peer");
+        server.start();
+        let port = server.bind_addrs().next().expect("Test peer not bound").1;
+        TestPeer {
+            _server: server,
+            service,
+            port,
+        }
+    }
+
+    pub fn uri(&self) -> String {
+        format!("insecure-mc://127.0.0.1:{}", self.port)
+    }
+
+    /// Answer get_latest_msg with the given raw payload.
+    pub fn set_payload(&self, payload: Vec<u8>) {
+        *self.service.payload.lock().unwrap() = payload;
+    }
+
+    /// Answer get_latest_msg with an unsigned ConsensusMsg from `sender` using `quorum_set`.
+    pub fn set_latest_msg(&self, sender: NodeID, quorum_set: QuorumSet) {
+        let topic = Topic::Externalize(ExternalizePayload {
+            C: Ballot::new(1, &[]),
+            HN: 1,
+        });
+        let msg = ConsensusMsg {
+            scp_msg: Msg::new(sender, quorum_set, 1, topic),
+            prev_block_id: Default::default(),
+            signature: Ed25519Signature::try_from(&[0u8; 64][..]).unwrap(),
+        };
+        self.set_payload(serialize(&msg).unwrap());
+    }
+}
+
+impl TestNetwork {
+    /// Starts `running` peers and reserves ports for `offline` nodes nobody listens on.
+    pub fn start(running: usize, offline: usize) -> Self {
+        let peers: Vec<TestPeer> = (0..running).map(|_| TestPeer::start()).collect();
+        let mut ports: Vec<u16> = peers.iter().map(|peer| peer.port).collect();
+        for _ in 0..offline {
+            // The listener is dropped right away so connecting to the port will fail
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            ports.push(listener.local_addr().unwrap().port());
+        }
+        TestNetwork { peers, ports }
+    }
+
+    pub fn node_id(&self, index: usize) -> NodeID {
+        NodeID {
+            responder_id: ResponderId::from_str(&format!("127.0.0.1:{}", self.ports[index]))
+                .unwrap(),
+            public_key: test_node_id(index as u32).public_key,
+        }
+    }
+
+    /// Writes the URIs of the given running peers to a bootstrap file in `dir`.
+    pub fn write_bootstrap_file(&self, dir: &std::path::Path, peers: &[usize]) -> String {
+        let path = dir.join("bootstrap.txt");
+        let uris: Vec<String> = peers.iter().map(|peer| self.peers[*peer].uri()).collect();
+        std::fs::write(&path, uris.join("\n")).unwrap();
+        path.display().to_string()
+    }
+
+    /// Makes a nodes JSON comparable to a golden file: public keys become "node<i>", ports
+    /// become the node's index, responder IDs "127.0.0.1:port<i>", RPC latencies are dropped and
+    /// nodes are sorted by public key.
+    pub fn normalise_nodes(&self, json: &str) -> Value {
+        let mut json = json.to_string();
+        for index in 0..self.ports.len() {
+            let key = STANDARD.encode(test_node_id(index as u32).public_key);
+            json = json.replace(&key, &format!("node{}", index));
+            json = json.replace(
+                &format!("\"127.0.0.1:{}\"", self.ports[index]),
+                &format!("\"127.0.0.1:port{}\"", index),
+            );
+        }
+        let mut nodes: Vec<Value> = serde_json::from_str(&json).unwrap();
+        for node in nodes.iter_mut() {
+            let port = node["port"].as_u64().unwrap();
+            if let Some(index) = self.ports.iter().position(|p| u64::from(*p) == port) {
+                node["port"] = Value::from(index);
+            }
+            // Timings differ between runs and the QSet hashes are taken before keys are replaced
+            if let Some(node) = node.as_object_mut() {
+                for volatile in [
+                    "rpcLatency",
+                    "dateDiscovered",
+                    "dateUpdated",
+                    "quorumSetHashKey",
+                ] {
+                    node.remove(volatile);
+                }
+            }
+        }
+        nodes.sort_by_key(|node| node["publicKey"].as_str().unwrap_or_default().to_string());
+        Value::Array(nodes)
+    }
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use assert_cmd::Command;
+use common::TestNetwork;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mc_consensus_scp::{QuorumSet, QuorumSetMember};
+
+/// Two running validators that trust each other and a third, offline, validator.
+fn three_node_network() -> TestNetwork {
+    let network = TestNetwork::start(2, 1);
+    let members = |nodes: &[usize]| -> Vec<QuorumSetMember<_>> {
+        nodes
+            .iter()
+            .map(|node| QuorumSetMember::Node(network.node_id(*node)))
+            .collect()
+    };
+    network.peers[0].set_latest_msg(network.node_id(0), QuorumSet::new(2, members(&[0, 1, 2])));
+    let mut quorum_set = members(&[0, 1]);
+    quorum_set.push(QuorumSetMember::InnerSet(QuorumSet::new(1, members(&[2]))));
+    network.peers[1].set_latest_msg(network.node_id(1), QuorumSet::new(2, quorum_set));
+    network
+}
+
+/// The output files in `dir` whose names start with `prefix`.
+fn output_files(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.starts_with(prefix))
+        })
+        .collect()
+}
+
+/// Waits until `dir` held output files with `count` different names starting with `prefix`, even
+/// if older ones have been pruned since. False if that didn't happen within `timeout`.
+fn wait_for_output_files(dir: &Path, prefix: &str, count: usize, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut seen = HashSet::new();
+    while Instant::now() < deadline {
+        seen.extend(output_files(dir, prefix));
+        if seen.len() >= count {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+#[test]
+fn crawl_synthetic_network() -> Result<(), Box<dyn std::error::Error>> {
+    let network = three_node_network();
+    let output = tempfile::tempdir()?;
+    let bootstrap = network.write_bootstrap_file(output.path(), &[0]);
+    let mut cmd = Command::cargo_bin("mc-crawler")?;
+    cmd.args(&[
+        "--fbas",
+        "--insecure",
+        "--retries",
+        "0",
+        "--connect-timeout",
+        "1",
+    ])
+    .arg("--output")
+    .arg(output.path())
+    .arg(bootstrap);
+    cmd.assert().success();
+
+    let nodes_file = output_files(output.path(), "mobilecoin_nodes_")
+        .pop()
+        .expect("No nodes file written");
+    let actual = network.normalise_nodes(&fs::read_to_string(nodes_file)?);
+    let expected: Value =
+        serde_json::from_str(&fs::read_to_string("tests/golden/three_node_network.json")?)?;
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn daemon_keeps_latest_crawls_and_stops_on_sigterm() -> Result<(), Box<dyn std::error::Error>> {
+    let network = three_node_network();
+    let output = tempfile::tempdir()?;
+    let bootstrap = network.write_bootstrap_file(output.path(), &[0]);
+    let mut daemon = std::process::Command::new(assert_cmd::cargo::cargo_bin("mc-crawler"))
+        .args(&[
+            "--fbas",
+            "--complete",
+            "--insecure",
+            "--retries",
+            "0",
+            "--connect-timeout",
+            "1",
+            "--interval",
+            "0.5",
+            "--keep",
+            "1",
+        ])
+        .arg("--output")
+        .arg(output.path())
+        .arg(bootstrap)
+        .spawn()?;
+    let crawled_twice = wait_for_output_files(
+        output.path(),
+        "mobilecoin_crawl_report_",
+        2,
+        Duration::from_secs(60),
+    );
+    let killed = std::process::Command::new("kill")
+        .args(&["-TERM", &daemon.id().to_string()])
+        .status()?;
+    assert!(killed.success());
+    assert!(daemon.wait()?.success());
+    assert!(crawled_twice, "The daemon didn't finish two crawls in time");
+
+    assert_eq!(output_files(output.path(), "mobilecoin_nodes_").len(), 1);
+    assert_eq!(
+        output_files(output.path(), "mobilecoin_crawl_report_").len(),
+        1
+    );
+    Ok(())
+}